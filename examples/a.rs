@@ -7,9 +7,9 @@ fn main() {
             "/home/saki/aosc-os_base_20240916_amd64.squashfs",
             "/test",
             None,
-            Box::new(move |c| {
+            |c| {
                 dbg!(c);
-            }),
+            },
         )
         .unwrap();
 }
@@ -0,0 +1,175 @@
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{denied, options::resolve_dest, ExtractOptions, Status, Unsquashfs, UnsquashfsError};
+
+/// Tells apart the temporary directories of concurrent extractions.
+static TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl Unsquashfs {
+    /// Extracts an image like [`Unsquashfs::extract_with`], so that the
+    /// destination either holds the whole image or is left untouched.
+    ///
+    /// The image is extracted into a temporary directory next to the
+    /// destination, which is renamed into place once `unsquashfs` succeeds
+    /// and removed if it fails or is cancelled. Cancelling returns
+    /// [`UnsquashfsError::Cancelled`].
+    ///
+    /// The destination must not exist yet or be an empty directory, otherwise
+    /// this fails with [`UnsquashfsError::DestinationNotEmpty`]. If it is on
+    /// another filesystem than its parent, such as a mount point, it can't
+    /// be renamed into and this fails with [`UnsquashfsError::CrossDevice`]
+    /// rather than copying, which wouldn't be atomic.
+    pub fn extract_atomic(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        callback: impl FnMut(i32),
+    ) -> Result<(), UnsquashfsError> {
+        let dest = resolve_dest(options.dest_dir()).map_err(denied(Some(options.dest_dir())))?;
+
+        if fs::read_dir(&dest).is_ok_and(|mut entries| entries.next().is_some()) || dest.is_file() {
+            return Err(UnsquashfsError::DestinationNotEmpty {
+                path: dest,
+                stderr: None,
+            });
+        }
+
+        let (parent, temp) = temp_sibling(&dest)?;
+        fs::create_dir_all(parent)?;
+
+        let res = self.extract_with(archive, &options.clone().dest(&temp), callback);
+
+        let res = match res {
+            Ok(()) if self.status() == Status::Cancelled => Err(UnsquashfsError::Cancelled),
+            Ok(()) => match fs::rename(&temp, &dest) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                    Err(UnsquashfsError::CrossDevice(dest))
+                }
+                Err(e) => Err(e.into()),
+            },
+            Err(e) => Err(e),
+        };
+
+        // `unsquashfs` may have failed before creating it.
+        match fs::remove_dir_all(&temp) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(UnsquashfsError::Cleanup(e)),
+            _ => res,
+        }
+    }
+}
+
+/// The parent of `dest` and a hidden directory in it to extract into.
+fn temp_sibling(dest: &Path) -> Result<(&Path, PathBuf), UnsquashfsError> {
+    let (Some(parent), Some(name)) = (dest.parent(), dest.file_name()) else {
+        return Err(UnsquashfsError::InvalidOption(format!(
+            "cannot extract atomically into `{}`",
+            dest.display()
+        )));
+    };
+
+    let mut temp = OsString::from(".");
+    temp.push(name);
+    temp.push(format!(
+        ".unsquashfs-{}-{}",
+        process::id(),
+        TEMP_ID.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    Ok((parent, parent.join(temp)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs, thread, time::Duration};
+
+    use crate::{test::fake_binary, ExtractOptions, Status, Unsquashfs, UnsquashfsError};
+
+    #[test]
+    fn test_extract_atomic() {
+        let binary = fake_binary(
+            "atomic",
+            r#"[ "$1" = -help ] && exit 0
+while [ "$1" != -d ]; do shift; done
+mkdir -p "$2/etc" && echo ok > "$2/etc/os-release"
+[ -e "$0.fail" ] && exit 1
+printf '[==========] 1/1 100%%\n'"#,
+        );
+        let root = temp_dir().join("unsqfs-wrap-test-atomic");
+        fs::remove_dir_all(&root).ok();
+        let dest = root.join("dest");
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+
+        fs::write(binary.with_extension("fail"), "").unwrap();
+        let err = unsquashfs
+            .extract_atomic(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().dest(&dest),
+                |_| {},
+            )
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::Failure { .. }));
+        // Nothing is left behind, not even the temporary directory.
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+
+        fs::remove_file(binary.with_extension("fail")).unwrap();
+        unsquashfs
+            .extract_atomic(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().dest(&dest),
+                |_| {},
+            )
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(dest.join("etc/os-release")).unwrap(),
+            "ok\n"
+        );
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+
+        let err = unsquashfs
+            .extract_atomic(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().dest(&dest),
+                |_| {},
+            )
+            .unwrap_err();
+        assert!(
+            matches!(err, UnsquashfsError::DestinationNotEmpty { path, stderr: None } if path == dest)
+        );
+    }
+
+    #[test]
+    fn test_extract_atomic_cancel() {
+        let binary = fake_binary(
+            "atomic-cancel",
+            r#"[ "$1" = -help ] && exit 0
+while [ "$1" != -d ]; do shift; done
+mkdir -p "$2/etc"
+printf '[=     ] 1/9  10%%\r'; exec sleep 10"#,
+        );
+        let root = temp_dir().join("unsqfs-wrap-test-atomic-cancel");
+        fs::remove_dir_all(&root).ok();
+        let dest = root.join("dest");
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let options = ExtractOptions::new().dest(&dest);
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_atomic("testdata/test_extract.squashfs", &options, |_| {})
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        unsquashfs.cancel().unwrap();
+        assert!(matches!(t.join().unwrap(), Err(UnsquashfsError::Cancelled)));
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use crate::UnsquashfsError;
+
+/// The squashfs extraction tool used to unpack images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// `unsquashfs` from squashfs-tools.
+    Unsquashfs,
+    /// `rdsquashfs` from squashfs-tools-ng.
+    ///
+    /// `rdsquashfs` has no progress bar, so the callback is only invoked
+    /// with `100` once the extraction finishes.
+    Rdsquashfs,
+}
+
+impl Backend {
+    fn binary_name(self) -> &'static str {
+        match self {
+            Backend::Unsquashfs => "unsquashfs",
+            Backend::Rdsquashfs => "rdsquashfs",
+        }
+    }
+
+    /// Guesses the backend of an explicitly configured binary from its file name.
+    fn from_binary(binary: &Path) -> Self {
+        match binary.file_name() {
+            Some(name) if name == "rdsquashfs" => Backend::Rdsquashfs,
+            _ => Backend::Unsquashfs,
+        }
+    }
+}
+
+/// Resolves the executable to spawn and the backend it implements.
+///
+/// Without an explicit binary or backend, `unsquashfs` is preferred and
+/// `rdsquashfs` is used as a fallback.
+pub(crate) fn resolve(
+    binary: Option<&Path>,
+    backend: Option<Backend>,
+) -> Result<(PathBuf, Backend), UnsquashfsError> {
+    if let Some(binary) = binary {
+        let backend = backend.unwrap_or_else(|| Backend::from_binary(binary));
+
+        return find(binary).map(|path| (path, backend));
+    }
+
+    let candidates: &[Backend] = match backend {
+        Some(backend) => &[backend],
+        None => &[Backend::Unsquashfs, Backend::Rdsquashfs],
+    };
+
+    let mut first_err = None;
+
+    for backend in candidates {
+        match find(Path::new(backend.binary_name())) {
+            Ok(path) => return Ok((path, *backend)),
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(first_err.expect("there is at least one candidate"))
+}
+
+/// Where the squashfs tools are often installed without being in the `PATH`
+/// of unprivileged users.
+const FALLBACK_DIRS: [&str; 3] = ["/usr/sbin", "/sbin", "/usr/local/sbin"];
+
+/// Looks up an executable in `PATH`, then in [`FALLBACK_DIRS`] if it is a
+/// bare name.
+pub(crate) fn find(binary: &Path) -> Result<PathBuf, UnsquashfsError> {
+    let fallbacks = FALLBACK_DIRS.map(PathBuf::from);
+
+    find_in(binary, env::var_os("PATH"), &fallbacks)
+}
+
+fn find_in(
+    binary: &Path,
+    search_path: Option<OsString>,
+    fallbacks: &[PathBuf],
+) -> Result<PathBuf, UnsquashfsError> {
+    let cwd = env::current_dir()?;
+
+    let source = match which::which_in(binary, search_path.as_ref(), &cwd) {
+        Ok(path) => return Ok(path),
+        Err(e) => e,
+    };
+
+    // A path with a directory is never looked up elsewhere.
+    let fallbacks = if binary.components().count() == 1 {
+        fallbacks.to_vec()
+    } else {
+        Vec::new()
+    };
+
+    fallbacks
+        .iter()
+        .find_map(|dir| which::which_in(binary, Some(dir), &cwd).ok())
+        .ok_or(UnsquashfsError::BinaryDoesNotExist {
+            binary: binary.to_path_buf(),
+            search_path,
+            fallbacks,
+            source,
+        })
+}
+
+/// Describes a failed [`find`] for [`UnsquashfsError::BinaryDoesNotExist`].
+pub(crate) fn not_found_message(
+    binary: &Path,
+    search_path: &Option<OsString>,
+    fallbacks: &[PathBuf],
+) -> String {
+    let mut msg = format!(
+        "`{}` binary does not exist in PATH `{}`",
+        binary.display(),
+        search_path.as_deref().unwrap_or_default().to_string_lossy()
+    );
+
+    if !fallbacks.is_empty() {
+        let dirs: Vec<_> = fallbacks
+            .iter()
+            .map(|dir| format!("`{}`", dir.display()))
+            .collect();
+        msg.push_str(&format!(" nor in {}", dirs.join(", ")));
+    }
+
+    msg + "."
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, ffi::OsString, fs, path::Path};
+
+    use super::find_in;
+    use crate::{test::fake_binary, ExtractOptions, Unsquashfs, UnsquashfsError};
+
+    #[test]
+    fn test_rdsquashfs_backend() {
+        let binary = fake_binary("rdsquashfs", "echo \"$@\" > \"$0.args\"");
+        let output = temp_dir().join("unsqfs-wrap-test-rdsquashfs");
+        fs::create_dir_all(&output).unwrap();
+
+        let mut progress = Vec::new();
+        Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().dest(&output).threads(2),
+                |c| progress.push(c),
+            )
+            .unwrap();
+
+        let args = fs::read_to_string(binary.with_extension("args")).unwrap();
+        assert!(args.starts_with(&format!(
+            "-q -u / -p {}",
+            output.canonicalize().unwrap().display()
+        )));
+        assert_eq!(progress, [100]);
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn test_find_fallback() {
+        let binary = fake_binary("sbin-only", "exit 0");
+        let sbin = binary.parent().unwrap().to_path_buf();
+        let empty = temp_dir().join("unsqfs-wrap-test-empty-path");
+        fs::create_dir_all(&empty).unwrap();
+
+        let search_path = Some(OsString::from(&empty));
+        let fallbacks = [empty.join("missing"), sbin];
+        let found = find_in(Path::new("sbin-only"), search_path.clone(), &fallbacks).unwrap();
+        assert_eq!(found, binary);
+
+        let err = find_in(Path::new("sbin-missing"), search_path, &fallbacks).unwrap_err();
+        assert!(matches!(
+            &err,
+            UnsquashfsError::BinaryDoesNotExist { binary, search_path: Some(path), fallbacks: tried, .. }
+                if binary == Path::new("sbin-missing") && *path == empty && *tried == fallbacks
+        ));
+        let msg = err.to_string();
+        assert!(msg.contains("`sbin-missing`") && msg.contains(&*empty.to_string_lossy()));
+        assert!(msg.contains(&*fallbacks[1].to_string_lossy()));
+
+        // Only bare names are looked up in the fallbacks.
+        let err = find_in(&empty.join("sbin-only"), None, &fallbacks).unwrap_err();
+        assert!(matches!(
+            err,
+            UnsquashfsError::BinaryDoesNotExist { search_path: None, fallbacks, .. } if fallbacks.is_empty()
+        ));
+    }
+}
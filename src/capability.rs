@@ -0,0 +1,219 @@
+use std::{path::Path, process};
+
+use crate::{
+    decompressors, denied, Backend, ExtractOptions, Unsquashfs, UnsquashfsError, XattrPolicy,
+};
+
+impl Unsquashfs {
+    /// Returns the `-help` output of the resolved binary, running it only once
+    /// per instance.
+    pub(crate) fn help(&self) -> Result<&str, UnsquashfsError> {
+        if let Some(help) = self.help.get() {
+            return Ok(help);
+        }
+
+        let (binary, _) = self.binary()?;
+        let output = process::Command::new(&binary)
+            .arg("-help")
+            .output()
+            .map_err(denied(Some(&binary)))?;
+
+        // Older versions print the usage to stderr and exit with an error.
+        let mut help = String::from_utf8_lossy(&output.stdout).into_owned();
+        help.push_str(&String::from_utf8_lossy(&output.stderr));
+
+        Ok(self.help.get_or_init(|| help))
+    }
+
+    /// Checks that the installed `unsquashfs` understands all of `flags`.
+    pub(crate) fn require_flags(&self, flags: &[&str]) -> Result<(), UnsquashfsError> {
+        if flags.is_empty() {
+            return Ok(());
+        }
+
+        let help = self.help()?;
+
+        match flags.iter().find(|flag| !mentions(help, flag)) {
+            Some(flag) => Err(UnsquashfsError::UnsupportedFlag(flag.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Swaps options the installed `unsquashfs` doesn't understand but can
+    /// do without for the closest supported ones.
+    pub(crate) fn degrade(
+        &self,
+        options: &ExtractOptions,
+    ) -> Result<ExtractOptions, UnsquashfsError> {
+        let mut options = options.clone();
+
+        if options.xattrs == XattrPolicy::UserOnly && !mentions(self.help()?, "-user-xattrs") {
+            options.xattrs = XattrPolicy::Skip;
+        }
+
+        // Added in 4.6, older versions only draw the progress bar.
+        if options.percentage && !mentions(self.help()?, "-percentage") {
+            options.percentage = false;
+        }
+
+        if options.no_progress && !mentions(self.help()?, "-no-progress") {
+            options.no_progress = false;
+        }
+
+        Ok(options)
+    }
+
+    /// Runs the checks of `options` that need `unsquashfs` before it is
+    /// spawned to extract `archive`, see [`ExtractOptions::check_compression`].
+    pub(crate) fn preflight(
+        &self,
+        archive: &Path,
+        options: &ExtractOptions,
+    ) -> Result<(), UnsquashfsError> {
+        if !options.check_compression || self.binary()?.1 != Backend::Unsquashfs {
+            return Ok(());
+        }
+
+        // Builds that don't list them are left to fail on their own.
+        let available = decompressors(self.help()?);
+        if available.is_empty() {
+            return Ok(());
+        }
+
+        let compression = self.stat_inner(archive, options.offset)?.compression;
+        if available.iter().any(|name| name == compression.name()) {
+            return Ok(());
+        }
+
+        Err(UnsquashfsError::UnsupportedCompression {
+            compression: compression.name().to_string(),
+            available,
+            stderr: String::new(),
+        })
+    }
+}
+
+/// Whether `help` documents `flag`, which it may abbreviate like `-r[egex]`.
+fn mentions(help: &str, flag: &str) -> bool {
+    let help: String = help.chars().filter(|c| !matches!(c, '[' | ']')).collect();
+
+    help.match_indices(flag).any(|(i, _)| {
+        let before = help[..i].chars().next_back();
+        let after = help[i + flag.len()..].chars().next();
+
+        before.is_none_or(char::is_whitespace)
+            && after.is_none_or(|c| !c.is_alphanumeric() && c != '-')
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::mentions;
+    use crate::{test::fake_binary, ExtractOptions, Unsquashfs, UnsquashfsError};
+
+    const HELP_4_6: &str = "\
+SYNTAX: unsquashfs [OPTIONS] FILESYSTEM [files to extract or exclude (with -excludes) or cat (with -cat )]
+
+Filesystem extraction (filtering) options:
+\t-d[est] <pathname>\textract to <pathname>, default \"squashfs-root\".
+\t-ex[clude-list] <list>\tlist of files to be excluded, terminated with ; e.g. file1 file2 ;.
+\t-r[egex]\t\ttreat extract names as POSIX regular expressions
+\t-o[ffset] <bytes>\tskip <bytes> at start of FILESYSTEM.";
+
+    #[test]
+    fn test_mentions() {
+        assert!(mentions(HELP_4_6, "-regex"));
+        assert!(mentions(HELP_4_6, "-exclude-list"));
+        assert!(mentions(HELP_4_6, "-offset"));
+        assert!(!mentions(HELP_4_6, "-excl"));
+        assert!(!mentions(HELP_4_6, "-percentage"));
+    }
+
+    #[test]
+    fn test_unsupported_regex() {
+        let binary = fake_binary(
+            "old-help",
+            "echo 'unsquashfs [options] filesystem' >&2; exit 1",
+        );
+
+        let err = Unsquashfs::with_binary(binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().paths(["usr/lib/.*"]).regex(true),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::UnsupportedFlag(flag) if flag == "-regex"));
+    }
+
+    #[test]
+    fn test_unsupported_mem_limit() {
+        let binary = fake_binary(
+            "old-help-mem",
+            "[ \"$1\" = -help ] && echo '-p[rocessors] <number>' && exit 0
+echo \"$@\" > \"$0.args\"",
+        );
+
+        let err = Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().mem_limit(64 << 20),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::UnsupportedFlag(flag) if flag == "-mem"));
+        assert!(!binary.with_extension("args").exists());
+    }
+
+    #[test]
+    fn test_check_compression() {
+        let binary = fake_binary(
+            "check-compression",
+            "[ \"$1\" = -help ] && printf 'Decompressors available:\\n\\tgzip\\n\\txz\\n' && exit 0
+[ \"$1\" = -s ] && cat <<'EOF' && exit 0
+Found a valid SQUASHFS 4:0 superblock on test.squashfs.
+Creation or last append time Mon Sep 16 10:00:00 2024
+Filesystem size 4096 bytes (4.00 Kbytes / 0.00 Mbytes)
+Compression zstd
+Block size 131072
+Number of fragments 1
+Number of inodes 5
+EOF
+echo \"$@\" > \"$0.args\"",
+        );
+        fs::remove_file(binary.with_extension("args")).ok();
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let err = unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().check_compression(true),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(matches!(
+            &err,
+            UnsquashfsError::UnsupportedCompression { compression, available, stderr }
+                if compression == "zstd" && *available == ["gzip", "xz"] && stderr.is_empty()
+        ));
+        assert!(err
+            .to_string()
+            .ends_with("does not support, only gzip, xz."));
+        assert!(!binary.with_extension("args").exists());
+
+        // Without the check, it is up to `unsquashfs` to fail.
+        unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap();
+        assert!(binary.with_extension("args").exists());
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{resolve_archive, scratch::Scratch, Status, Unsquashfs, UnsquashfsError};
+
+impl Unsquashfs {
+    /// Writes the contents of a single file inside an image to `writer`
+    /// (`unsquashfs -cat`), returning the number of bytes written.
+    ///
+    /// The file is streamed, so it is never held in memory as a whole. This
+    /// needs squashfs-tools 4.6 or newer.
+    pub fn cat_file(
+        &self,
+        archive: impl AsRef<Path>,
+        inner_path: impl AsRef<Path>,
+        writer: &mut impl Write,
+    ) -> Result<u64, UnsquashfsError> {
+        self.require_flags(&["-cat"])?;
+
+        let archive = resolve_archive(archive.as_ref())?;
+        let inner_path = inner_path.as_ref();
+
+        self.run(
+            &["-cat".into(), archive.into(), inner_path.into()],
+            |mut stdout| io::copy(&mut stdout, writer),
+        )
+        .map_err(|e| match e {
+            UnsquashfsError::Failure { stderr, .. } if is_not_found(&stderr) => {
+                UnsquashfsError::FileNotFound(inner_path.to_path_buf())
+            }
+            UnsquashfsError::Failure { stderr, .. } if is_not_file(&stderr) => {
+                UnsquashfsError::NotAFile(inner_path.to_path_buf())
+            }
+            e => e,
+        })
+    }
+
+    /// Reads a single small file inside an image, such as `/etc/os-release`,
+    /// into memory.
+    ///
+    /// This uses [`Unsquashfs::cat_file`] if the installed `unsquashfs`
+    /// supports it, and otherwise extracts just that file into a scratch
+    /// directory under [`std::env::temp_dir`] and reads it back. A path that is
+    /// not in the image fails with [`UnsquashfsError::FileNotFound`], and a
+    /// directory or symlink with [`UnsquashfsError::NotAFile`].
+    pub fn read_file(
+        &self,
+        archive: impl AsRef<Path>,
+        inner_path: impl AsRef<Path>,
+    ) -> Result<Vec<u8>, UnsquashfsError> {
+        let (archive, inner_path) = (archive.as_ref(), inner_path.as_ref());
+        let mut buf = Vec::new();
+
+        match self.cat_file(archive, inner_path, &mut buf) {
+            Ok(_) => Ok(buf),
+            Err(UnsquashfsError::UnsupportedFlag(_)) => self.read_extracted(archive, inner_path),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_extracted(
+        &self,
+        archive: &Path,
+        inner_path: &Path,
+    ) -> Result<Vec<u8>, UnsquashfsError> {
+        let scratch = Scratch::new("read")?;
+        let options = scratch.options().paths([inner_path]);
+        let relative: PathBuf = inner_path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+
+        let res = self
+            .extract_no_progress(archive, &options)
+            .and_then(|()| match self.status() {
+                Status::Cancelled => Err(UnsquashfsError::Cancelled),
+                _ => read_regular(&scratch.path().join(relative), inner_path),
+            });
+
+        // Leftovers only matter if the file could be read.
+        let cleanup = scratch.remove();
+        res.and_then(|buf| cleanup.map(|()| buf))
+    }
+}
+
+/// Reads `file`, extracted from `inner_path` in the image, without following
+/// symlinks out of the scratch directory.
+fn read_regular(file: &Path, inner_path: &Path) -> Result<Vec<u8>, UnsquashfsError> {
+    match fs::symlink_metadata(file) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(UnsquashfsError::FileNotFound(inner_path.to_path_buf()))
+        }
+        Err(e) => Err(e.into()),
+        Ok(metadata) if !metadata.is_file() => {
+            Err(UnsquashfsError::NotAFile(inner_path.to_path_buf()))
+        }
+        Ok(_) => Ok(fs::read(file)?),
+    }
+}
+
+/// Whether `unsquashfs -cat` failed because the path is not in the image.
+fn is_not_found(stderr: &str) -> bool {
+    stderr.contains("does not exist") || stderr.contains("not found")
+}
+
+/// Whether `unsquashfs -cat` failed because the path is not a regular file.
+fn is_not_file(stderr: &str) -> bool {
+    stderr.contains("is a directory") || stderr.contains("not a regular file")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use crate::{test::fake_binary, Unsquashfs, UnsquashfsError};
+
+    const CAT: &str = r#"
+case "$1" in
+    -help) echo '	-cat	cat the files on the command line to stdout' ;;
+    *) case "$3" in
+        etc/os-release) printf 'NAME="AOSC OS"\n' ;;
+        etc) echo "cat: $3 is a directory" >&2; exit 1 ;;
+        *) echo "cat: $3 does not exist in filesystem" >&2; exit 1 ;;
+    esac ;;
+esac"#;
+
+    #[test]
+    fn test_cat_file() {
+        let unsquashfs = Unsquashfs::with_binary(fake_binary("cat", CAT));
+        let mut buf = Vec::new();
+
+        let written = unsquashfs
+            .cat_file("testdata/test_extract.squashfs", "etc/os-release", &mut buf)
+            .unwrap();
+
+        assert_eq!(buf, b"NAME=\"AOSC OS\"\n");
+        assert_eq!(written, buf.len() as u64);
+
+        let err = unsquashfs
+            .cat_file("testdata/test_extract.squashfs", "etc/missing", &mut buf)
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::FileNotFound(p) if p.ends_with("missing")));
+    }
+
+    #[test]
+    fn test_read_file() {
+        let unsquashfs = Unsquashfs::with_binary(fake_binary("read-cat", CAT));
+
+        let contents = unsquashfs
+            .read_file("testdata/test_extract.squashfs", "etc/os-release")
+            .unwrap();
+        assert_eq!(contents, b"NAME=\"AOSC OS\"\n");
+
+        let err = unsquashfs
+            .read_file("testdata/test_extract.squashfs", "etc")
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::NotAFile(p) if p == Path::new("etc")));
+    }
+
+    #[test]
+    fn test_read_file_extracted() {
+        // Older versions without `-cat`.
+        let binary = fake_binary(
+            "read-extract",
+            r#"[ "$1" = -help ] && echo '-no-xattrs' && exit 0
+while [ "$1" != -d ]; do shift; done
+dest="$2"
+echo "$dest" > "$0.dest"
+case "$*" in
+    *etc/os-release) mkdir -p "$dest/etc"; echo 'NAME="AOSC OS"' > "$dest/etc/os-release" ;;
+    *etc) mkdir -p "$dest/etc" ;;
+esac"#,
+        );
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+
+        let contents = unsquashfs
+            .read_file("testdata/test_extract.squashfs", "/etc/os-release")
+            .unwrap();
+        assert_eq!(contents, b"NAME=\"AOSC OS\"\n");
+
+        let err = unsquashfs
+            .read_file("testdata/test_extract.squashfs", "/etc")
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::NotAFile(_)));
+
+        let err = unsquashfs
+            .read_file("testdata/test_extract.squashfs", "/etc/missing")
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::FileNotFound(_)));
+
+        // The scratch directory is gone.
+        let scratch = fs::read_to_string(binary.with_extension("dest")).unwrap();
+        assert!(!Path::new(scratch.trim_end()).exists());
+    }
+}
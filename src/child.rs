@@ -0,0 +1,125 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{ExtractOptions, UnsquashfsError};
+
+/// I/O scheduling class of the spawned process, see
+/// [`ExtractOptions::io_priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Only do I/O when no other process needs the disk.
+    Idle,
+    /// Share the disk with other processes at a level from 0, the highest,
+    /// to 7, the lowest.
+    BestEffort(u8),
+}
+
+impl IoPriority {
+    pub(crate) fn validate(self) -> Result<(), UnsquashfsError> {
+        match self {
+            IoPriority::BestEffort(level) if level > 7 => Err(UnsquashfsError::InvalidOption(
+                format!("I/O priority level {level} must be between 0 and 7"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The value `ioprio_set` takes, which is the class in the top bits and
+    /// the level below.
+    #[cfg(target_os = "linux")]
+    fn value(self) -> libc::c_int {
+        const CLASS_SHIFT: libc::c_int = 13;
+
+        match self {
+            IoPriority::Idle => 3 << CLASS_SHIFT,
+            IoPriority::BestEffort(level) => 2 << CLASS_SHIFT | libc::c_int::from(level),
+        }
+    }
+}
+
+/// Builds what to run in the forked child before it execs `unsquashfs`, to
+/// confine it as `options` ask, if they ask for anything.
+///
+/// The cgroup is opened here in the parent, so that being denied fails with
+/// [`UnsquashfsError::CgroupDenied`] rather than a bare spawn error.
+pub(crate) fn pre_exec(
+    options: &ExtractOptions,
+) -> Result<Option<impl FnMut() -> io::Result<()> + Send + Sync + 'static>, UnsquashfsError> {
+    let (nice, io_priority) = (options.nice, options.io_priority);
+    let cgroup = options.cgroup.as_deref().map(open_cgroup).transpose()?;
+
+    let setup = move || {
+        // Join the cgroup first, so that its limits already apply to
+        // whatever `unsquashfs` allocates.
+        if let Some(mut procs) = cgroup.as_ref() {
+            // 0 is the writing process, which saves formatting the PID
+            // after forking.
+            procs.write_all(b"0")?;
+        }
+
+        if let Some(nice) = nice {
+            set_nice(nice)?;
+        }
+
+        if let Some(io_priority) = io_priority {
+            set_io_priority(io_priority)?;
+        }
+
+        Ok(())
+    };
+
+    Ok((nice.is_some() || io_priority.is_some() || options.cgroup.is_some()).then_some(setup))
+}
+
+/// Opens `cgroup.procs` of the cgroup v2 directory `cgroup` for writing.
+fn open_cgroup(cgroup: &Path) -> Result<File, UnsquashfsError> {
+    OpenOptions::new()
+        .write(true)
+        .open(cgroup.join("cgroup.procs"))
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                UnsquashfsError::InvalidOption(format!("`{}` is not a cgroup", cgroup.display()))
+            }
+            io::ErrorKind::PermissionDenied => UnsquashfsError::CgroupDenied(cgroup.to_path_buf()),
+            _ => e.into(),
+        })
+}
+
+fn set_nice(nice: i32) -> io::Result<()> {
+    // SAFETY: setpriority has no memory safety requirements. Process 0 is
+    // the calling process, which is the child after forking.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_io_priority(io_priority: IoPriority) -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+    // SAFETY: ioprio_set only takes integers.
+    if unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            io_priority.value(),
+        )
+    } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Only Linux has I/O priorities.
+#[cfg(not(target_os = "linux"))]
+fn set_io_priority(_: IoPriority) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Paths under a destination directory before an extraction started, so
+/// that only what the extraction created is removed when it is cancelled.
+pub(crate) struct Snapshot {
+    root: PathBuf,
+    existing: HashSet<PathBuf>,
+}
+
+impl Snapshot {
+    pub(crate) fn take(root: &Path) -> io::Result<Self> {
+        let mut existing = HashSet::new();
+
+        if root.symlink_metadata().is_ok() {
+            existing.insert(root.to_path_buf());
+            collect(root, &mut existing)?;
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            existing,
+        })
+    }
+
+    /// Removes everything under the root that is not in the snapshot.
+    ///
+    /// A path that can't be removed, such as for lack of permissions, doesn't
+    /// stop the others from being removed, and the first such error is
+    /// returned at the end.
+    pub(crate) fn remove_new(&self) -> io::Result<()> {
+        let mut first_error = None;
+        self.remove_new_in(&self.root, &mut first_error);
+
+        first_error.map_or(Ok(()), Err)
+    }
+
+    fn remove_new_in(&self, path: &Path, first_error: &mut Option<io::Error>) {
+        let mut fail = |e: io::Error| {
+            diag!(warn, "failed to remove {}: {e}", path.display());
+            first_error.get_or_insert(e);
+        };
+
+        let metadata = match path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => return fail(e),
+        };
+
+        if !self.existing.contains(path) {
+            let res = match metadata.is_dir() {
+                true => fs::remove_dir_all(path),
+                false => fs::remove_file(path),
+            };
+
+            return res.unwrap_or_else(fail);
+        }
+
+        if metadata.is_dir() {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(e) => return fail(e),
+            };
+
+            for entry in entries {
+                match entry {
+                    Ok(entry) => self.remove_new_in(&entry.path(), first_error),
+                    Err(e) => {
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Adds every path under the directory `dir` to `paths`, without following
+/// symlinks.
+fn collect(dir: &Path, paths: &mut HashSet<PathBuf>) -> io::Result<()> {
+    if !dir.symlink_metadata()?.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        collect(&path, paths)?;
+        paths.insert(path);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs, os::unix::fs::symlink};
+
+    use super::Snapshot;
+
+    #[test]
+    fn test_remove_new() {
+        let root = temp_dir().join("unsqfs-wrap-test-cleanup");
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/user-data"), "keep").unwrap();
+
+        let snapshot = Snapshot::take(&root).unwrap();
+
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/sh"), "").unwrap();
+        fs::write(root.join("etc/os-release"), "").unwrap();
+
+        snapshot.remove_new().unwrap();
+
+        assert!(root.join("etc/user-data").exists());
+        assert!(!root.join("etc/os-release").exists());
+        assert!(!root.join("usr").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_keep_symlink_targets() {
+        let root = temp_dir().join("unsqfs-wrap-test-cleanup-symlink");
+        let outside = temp_dir().join("unsqfs-wrap-test-cleanup-outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        symlink(&outside, root.join("data")).ok();
+
+        let snapshot = Snapshot::take(&root).unwrap();
+
+        // Written through the symlink, so outside of the destination.
+        fs::write(root.join("data/new"), "").unwrap();
+        fs::write(root.join("new"), "").unwrap();
+
+        snapshot.remove_new().unwrap();
+
+        assert!(outside.join("new").exists());
+        assert!(root.join("data").exists());
+        assert!(!root.join("new").exists());
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(outside).unwrap();
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    process::Stdio,
+    thread,
+};
+
+use pty_process::{
+    blocking::{Command, Pty},
+    Size,
+};
+
+use crate::{
+    backend, extraction_failed, logging,
+    progress::{handle, percent_only, Output},
+    read_stderr, spawn_denied, Compression, ErrorMode, Progress, Unsquashfs, UnsquashfsError,
+    CLEARED_ENV, DEFAULT_CANCEL_GRACE,
+};
+
+/// Options controlling how an image is created by [`Unsquashfs::create`] or
+/// appended to by [`Unsquashfs::append`].
+#[derive(Debug, Clone, Default)]
+pub struct CreateOptions {
+    compression: Option<Compression>,
+    block_size: Option<u32>,
+    processors: Option<usize>,
+    no_duplicates: bool,
+    keep_as_directory: bool,
+}
+
+impl CreateOptions {
+    pub fn new() -> Self {
+        CreateOptions::default()
+    }
+
+    /// Compression algorithm of the image (`-comp`), `mksquashfs` defaults
+    /// to gzip. Appending keeps the one of the existing image.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Size of data blocks in bytes (`-b`), a power of two between 4 KiB and
+    /// 1 MiB. `mksquashfs` defaults to 128 KiB. Appending keeps the one of the
+    /// existing image.
+    pub fn block_size(mut self, block_size: u32) -> Self {
+        self.block_size = Some(block_size);
+        self
+    }
+
+    /// Limit the number of processors `mksquashfs` uses (`-processors`).
+    pub fn processors(mut self, processors: usize) -> Self {
+        self.processors = Some(processors);
+        self
+    }
+
+    /// Store identical files as many times as they occur instead of once
+    /// (`-no-duplicates`).
+    pub fn no_duplicates(mut self, no_duplicates: bool) -> Self {
+        self.no_duplicates = no_duplicates;
+        self
+    }
+
+    /// Put the source directory itself in the root of the image, rather than
+    /// only what it contains (`-keep-as-directory`).
+    pub fn keep_as_directory(mut self, keep: bool) -> Self {
+        self.keep_as_directory = keep;
+        self
+    }
+
+    fn validate(&self) -> Result<(), UnsquashfsError> {
+        if self.processors == Some(0) {
+            return Err(UnsquashfsError::InvalidOption(
+                "processor count must be greater than 0".to_string(),
+            ));
+        }
+
+        if let Some(block_size) = self.block_size {
+            if !block_size.is_power_of_two() || !(4096..=1 << 20).contains(&block_size) {
+                return Err(UnsquashfsError::InvalidOption(format!(
+                    "block size {block_size} is not a power of two between 4 KiB and 1 MiB"
+                )));
+            }
+        }
+
+        if let Some(Compression::Unknown(name)) = &self.compression {
+            if name.is_empty() || name.starts_with('-') {
+                return Err(UnsquashfsError::InvalidOption(format!(
+                    "invalid compression `{name}`"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the command line for packing `source` into `output`, adding to
+    /// what `output` already contains if `append` is set.
+    fn args(&self, source: &Path, output: &Path, append: bool) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![source.into(), output.into()];
+
+        // Without `-noappend`, an existing image is appended to.
+        if !append {
+            args.push("-noappend".into());
+        }
+
+        if let Some(compression) = &self.compression {
+            args.push("-comp".into());
+            args.push(compression.name().into());
+        }
+
+        if let Some(block_size) = self.block_size {
+            args.push("-b".into());
+            args.push(block_size.to_string().into());
+        }
+
+        if let Some(processors) = self.processors {
+            args.push("-processors".into());
+            args.push(processors.to_string().into());
+        }
+
+        if self.no_duplicates {
+            args.push("-no-duplicates".into());
+        }
+
+        if self.keep_as_directory {
+            args.push("-keep-as-directory".into());
+        }
+
+        args
+    }
+}
+
+impl Unsquashfs {
+    /// Packs the directory `source` into a new image at `output` with
+    /// `mksquashfs`, overwriting it if it exists.
+    ///
+    /// `mksquashfs` is looked up next to the binary given to
+    /// [`Unsquashfs::with_binary`], then in `PATH`. `callback` receives the
+    /// percentage like with [`Unsquashfs::extract`], and
+    /// [`Unsquashfs::cancel`] kills `mksquashfs` and removes the partly
+    /// written `output`.
+    pub fn create(
+        &self,
+        source: impl AsRef<Path>,
+        output: impl AsRef<Path>,
+        options: &CreateOptions,
+        callback: impl FnMut(i32),
+    ) -> Result<(), UnsquashfsError> {
+        let (source, output) = (source.as_ref(), output.as_ref());
+
+        options.validate()?;
+
+        let cancelled = self.mksquashfs_run(&options.args(source, output, false), callback)?;
+
+        if cancelled {
+            match fs::remove_file(output) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    return Err(UnsquashfsError::Cleanup(e))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the contents of the directory `source` to the existing image at
+    /// `image` with `mksquashfs`, like [`Unsquashfs::create`] does for a new
+    /// one.
+    ///
+    /// Fails with [`UnsquashfsError::FileNotFound`] if `image` doesn't exist.
+    /// Cancelling leaves `image` as `mksquashfs` was killed, which may be
+    /// damaged.
+    pub fn append(
+        &self,
+        source: impl AsRef<Path>,
+        image: impl AsRef<Path>,
+        options: &CreateOptions,
+        callback: impl FnMut(i32),
+    ) -> Result<(), UnsquashfsError> {
+        let (source, image) = (source.as_ref(), image.as_ref());
+
+        options.validate()?;
+
+        if !image.is_file() {
+            return Err(UnsquashfsError::FileNotFound(image.to_path_buf()));
+        }
+
+        self.mksquashfs_run(&options.args(source, image, true), callback)?;
+
+        Ok(())
+    }
+
+    /// Runs `mksquashfs` with `args`, returning whether it was cancelled.
+    fn mksquashfs_run(
+        &self,
+        args: &[OsString],
+        callback: impl FnMut(i32),
+    ) -> Result<bool, UnsquashfsError> {
+        let claim = self.begin()?;
+        let binary = self.mksquashfs()?;
+
+        let pty = Pty::new()?;
+        pty.resize(Size::new(30, 80))?;
+
+        diag!(debug, "spawning {}", logging::command_line(&binary, args));
+
+        let mut command = Command::new(&binary);
+
+        for key in CLEARED_ENV {
+            command.env_remove(key);
+        }
+
+        let mut child = command
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn(&pty.pts()?)
+            .map_err(spawn_denied(Some(&binary)))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Failed to get stdout"))?;
+
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Failed to get stderr"))?;
+
+        let process_control = self.control(claim, child, ErrorMode::Default, DEFAULT_CANCEL_GRACE);
+        let stderr = thread::spawn(move || read_stderr(stderr, None));
+
+        let mut callback = percent_only(callback);
+        handle(
+            stdout,
+            Output::default(),
+            |progress: Progress| {
+                self.set_percent(progress);
+                callback(progress);
+            },
+            |_| {},
+        )?;
+
+        let buf = stderr.join().unwrap();
+        logging::stderr(&buf);
+
+        match process_control.join() {
+            Ok(Some(wait)) if !wait.success() => Err(extraction_failed(wait, buf, None, None)),
+            Ok(wait) => Ok(wait.is_none()),
+            Err(source) => Err(UnsquashfsError::Failure {
+                source,
+                stderr: buf,
+                exit_code: None,
+                signal: None,
+            }),
+        }
+    }
+
+    /// Finds `mksquashfs`, preferring the one installed alongside the
+    /// configured `unsquashfs`.
+    fn mksquashfs(&self) -> Result<PathBuf, UnsquashfsError> {
+        let alongside = self
+            .binary
+            .as_deref()
+            .and_then(|binary| which::which(binary).ok())
+            .map(|binary| binary.with_file_name("mksquashfs"))
+            .filter(|path| path.is_file());
+
+        match alongside {
+            Some(path) => Ok(path),
+            None => backend::find(Path::new("mksquashfs")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, ffi::OsString, fs, path::Path, thread, time::Duration};
+
+    use super::CreateOptions;
+    use crate::{
+        test::fake_binary, Compression, ExtractOptions, Status, Unsquashfs, UnsquashfsError,
+    };
+
+    #[test]
+    fn test_create_args() {
+        let options = CreateOptions::new()
+            .compression(Compression::Zstd)
+            .block_size(1 << 20)
+            .processors(2);
+
+        let args = options.args(Path::new("root"), Path::new("out.squashfs"), false);
+        let expected: [OsString; 9] = [
+            "root".into(),
+            "out.squashfs".into(),
+            "-noappend".into(),
+            "-comp".into(),
+            "zstd".into(),
+            "-b".into(),
+            "1048576".into(),
+            "-processors".into(),
+            "2".into(),
+        ];
+        assert_eq!(args, expected);
+
+        for options in [
+            CreateOptions::new().block_size(3000),
+            CreateOptions::new().block_size(2 << 20),
+            CreateOptions::new().processors(0),
+        ] {
+            assert!(matches!(
+                options.validate(),
+                Err(UnsquashfsError::InvalidOption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_create_progress() {
+        fake_binary(
+            "create/mksquashfs",
+            "printf '[=====     ] 1/2  50%%\\r[==========] 2/2 100%%\\n'
+echo image > \"$2\"",
+        );
+        let unsquashfs = Unsquashfs::with_binary(fake_binary("create/unsquashfs", "exit 0"));
+        let output = temp_dir().join("unsqfs-wrap-test-create-progress.squashfs");
+
+        let mut progress = Vec::new();
+        unsquashfs
+            .create("testdata", &output, &CreateOptions::new(), |p| {
+                progress.push(p)
+            })
+            .unwrap();
+
+        assert_eq!(progress, [50, 100]);
+        assert_eq!(fs::read_to_string(&output).unwrap(), "image\n");
+        assert_eq!(unsquashfs.status(), Status::Done);
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_create_cancel() {
+        fake_binary(
+            "create-cancel/mksquashfs",
+            "echo partial > \"$2\"
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+        let unsquashfs = Unsquashfs::with_binary(fake_binary("create-cancel/unsquashfs", "exit 0"));
+        let output = temp_dir().join("unsqfs-wrap-test-create-cancel.squashfs");
+
+        let unsquashfs_clone = unsquashfs.clone();
+        let output_clone = output.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.create("testdata", output_clone, &CreateOptions::new(), |_| {})
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        unsquashfs.cancel().unwrap();
+        t.join().unwrap().unwrap();
+
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_create_round_trip() {
+        let source = temp_dir().join("unsqfs-wrap-test-create-source");
+        fs::create_dir_all(source.join("etc")).unwrap();
+        fs::write(source.join("etc/os-release"), "NAME=AOSC OS\n").unwrap();
+
+        let image = temp_dir().join("unsqfs-wrap-test-create-round-trip.squashfs");
+        let unsquashfs = Unsquashfs::new();
+        unsquashfs
+            .create(
+                &source,
+                &image,
+                &CreateOptions::new().compression(Compression::Xz),
+                |_| {},
+            )
+            .unwrap();
+
+        let dest = temp_dir().join("unsqfs-wrap-test-create-dest");
+        fs::create_dir_all(&dest).unwrap();
+        unsquashfs
+            .extract_with(&image, &ExtractOptions::new().dest(&dest), |_| {})
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dest.join("etc/os-release")).unwrap(),
+            "NAME=AOSC OS\n"
+        );
+        assert_eq!(
+            unsquashfs.stat(&image).unwrap().compression,
+            Compression::Xz
+        );
+
+        fs::remove_dir_all(source).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+        fs::remove_file(image).unwrap();
+    }
+
+    #[test]
+    fn test_append_args() {
+        let options = CreateOptions::new()
+            .no_duplicates(true)
+            .keep_as_directory(true);
+
+        let args = options.args(Path::new("locale"), Path::new("base.squashfs"), true);
+        let expected: [OsString; 4] = [
+            "locale".into(),
+            "base.squashfs".into(),
+            "-no-duplicates".into(),
+            "-keep-as-directory".into(),
+        ];
+        assert_eq!(args, expected);
+
+        let err = Unsquashfs::new()
+            .append(
+                "testdata",
+                temp_dir().join("unsqfs-wrap-test-append-missing.squashfs"),
+                &options,
+                |_| {},
+            )
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::FileNotFound(_)));
+    }
+
+    #[test]
+    fn test_append_round_trip() {
+        let base = temp_dir().join("unsqfs-wrap-test-append-base");
+        let locale = temp_dir().join("unsqfs-wrap-test-append-locale");
+        fs::create_dir_all(base.join("etc")).unwrap();
+        fs::create_dir_all(locale.join("usr/share/locale")).unwrap();
+        fs::write(base.join("etc/os-release"), "NAME=AOSC OS\n").unwrap();
+        fs::write(locale.join("usr/share/locale/zh_CN"), "").unwrap();
+
+        let image = temp_dir().join("unsqfs-wrap-test-append.squashfs");
+        let unsquashfs = Unsquashfs::new();
+        unsquashfs
+            .create(&base, &image, &CreateOptions::new(), |_| {})
+            .unwrap();
+
+        let mut progress = Vec::new();
+        unsquashfs
+            .append(&locale, &image, &CreateOptions::new(), |p| progress.push(p))
+            .unwrap();
+        assert_eq!(progress.last(), Some(&100));
+
+        let paths = unsquashfs.list(&image).unwrap();
+        assert!(paths.contains(&"/etc/os-release".to_string()));
+        assert!(paths.contains(&"/usr/share/locale/zh_CN".to_string()));
+
+        fs::remove_dir_all(base).unwrap();
+        fs::remove_dir_all(locale).unwrap();
+        fs::remove_file(image).unwrap();
+    }
+}
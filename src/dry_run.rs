@@ -0,0 +1,151 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    list::parse_long_line, options::check_offset, resolve_archive, EntryKind, ExtractOptions,
+    Unsquashfs, UnsquashfsError,
+};
+
+/// Regular files and their total size, under some directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// What an extraction would write, from [`Unsquashfs::dry_run`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DryRun {
+    /// Every path that would be created, relative to the destination with a
+    /// leading `/`.
+    pub paths: Vec<PathBuf>,
+    /// Regular files in the whole extraction.
+    pub total: Usage,
+    /// Regular files under each top-level entry, such as `/usr`.
+    pub top_level: BTreeMap<PathBuf, Usage>,
+}
+
+impl Unsquashfs {
+    /// Reports what extracting `archive` with `options` would write, from a
+    /// listing (`unsquashfs -ll`), without touching the destination.
+    ///
+    /// [`ExtractOptions::paths`], [`ExtractOptions::exclude`],
+    /// [`ExtractOptions::regex`], [`ExtractOptions::follow_symlinks`] and
+    /// [`ExtractOptions::offset`] select the same entries as they would for
+    /// the extraction. The other options don't change what is written and
+    /// are ignored.
+    pub fn dry_run(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+    ) -> Result<DryRun, UnsquashfsError> {
+        options.validate()?;
+        self.require_flags(&options.filter_flags())?;
+
+        let archive = resolve_archive(archive.as_ref())?;
+
+        if let Some(offset) = options.offset {
+            check_offset(&archive, offset)?;
+        }
+
+        let mut args: Vec<OsString> = vec!["-ll".into()];
+        options.push_filter_args(&mut args);
+        args.push(archive.into());
+        args.extend(options.path_args());
+
+        let mut report = DryRun::default();
+
+        self.run_lines(&args, |line| {
+            let Some(entry) = parse_long_line(line) else {
+                return;
+            };
+
+            if entry.kind == EntryKind::File {
+                let top_level = match entry.path.components().nth(1) {
+                    Some(Component::Normal(name)) => Path::new("/").join(name),
+                    _ => entry.path.clone(),
+                };
+                let usage = report.top_level.entry(top_level).or_default();
+
+                for usage in [usage, &mut report.total] {
+                    usage.files += 1;
+                    usage.bytes += entry.size;
+                }
+            }
+
+            report.paths.push(entry.path);
+        })?;
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use super::Usage;
+    use crate::{test::fake_binary, ExtractOptions, Unsquashfs};
+
+    #[test]
+    fn test_dry_run() {
+        let binary = fake_binary(
+            "dry-run",
+            r#"case "$1" in
+    -help) echo '-exclude-list' ;;
+    *) echo "$@" > "$0.args"; cat <<'EOF'
+drwxr-xr-x root/root                38 2024-09-16 10:00 squashfs-root
+drwxr-xr-x root/root                38 2024-09-16 10:00 squashfs-root/etc
+-rw-r--r-- root/root               123 2024-09-16 10:00 squashfs-root/etc/os-release
+-rw-r--r-- root/root              4096 2024-09-16 10:00 squashfs-root/etc/fstab
+drwxr-xr-x root/root                38 2024-09-16 10:00 squashfs-root/usr
+-rw-r--r-- root/root              1000 2024-09-16 10:00 squashfs-root/usr/bin
+lrwxrwxrwx root/root                 7 2024-09-16 10:00 squashfs-root/bin -> usr/bin
+EOF
+    ;;
+esac"#,
+        );
+
+        let options = ExtractOptions::new()
+            .paths(["/etc", "/usr"])
+            .exclude(["var/cache"]);
+        let report = Unsquashfs::with_binary(&binary)
+            .dry_run("testdata/test_extract.squashfs", &options)
+            .unwrap();
+
+        assert_eq!(report.paths.len(), 6);
+        assert_eq!(
+            report.total,
+            Usage {
+                files: 3,
+                bytes: 5219
+            }
+        );
+        assert_eq!(
+            report.top_level.into_iter().collect::<Vec<_>>(),
+            [
+                (
+                    PathBuf::from("/etc"),
+                    Usage {
+                        files: 2,
+                        bytes: 4219
+                    }
+                ),
+                (
+                    PathBuf::from("/usr"),
+                    Usage {
+                        files: 1,
+                        bytes: 1000
+                    }
+                ),
+            ]
+        );
+
+        let args = fs::read_to_string(binary.with_extension("args")).unwrap();
+        assert!(args.starts_with("-ll -exclude-list var/cache ; /"));
+        assert!(args.ends_with(" etc usr\n"));
+    }
+}
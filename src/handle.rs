@@ -0,0 +1,252 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{ExtractOptions, ProgressEvent, Unsquashfs, UnsquashfsError};
+
+/// An extraction running in the background, see [`Unsquashfs::extract_start`].
+///
+/// Dropping the handle before the extraction finishes cancels it and waits
+/// for `unsquashfs` to exit.
+pub struct ExtractHandle {
+    unsquashfs: Unsquashfs,
+    percent: Arc<AtomicU8>,
+    /// Set once this handle's run has spawned `unsquashfs`.
+    spawned: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), UnsquashfsError>>>,
+}
+
+impl ExtractHandle {
+    /// The last reported percentage.
+    pub fn progress(&self) -> u8 {
+        self.percent.load(Ordering::SeqCst)
+    }
+
+    /// Whether the extraction is over, so that [`ExtractHandle::wait`] won't
+    /// block.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Cancels the extraction like [`Unsquashfs::cancel`], failing with
+    /// [`UnsquashfsError::Pending`] if `unsquashfs` isn't spawned yet.
+    pub fn cancel(&self) -> Result<(), UnsquashfsError> {
+        self.unsquashfs.cancel()
+    }
+
+    /// Waits for the extraction to finish and returns its outcome.
+    pub fn wait(mut self) -> Result<(), UnsquashfsError> {
+        self.thread.take().unwrap().join().unwrap()
+    }
+}
+
+impl Drop for ExtractHandle {
+    fn drop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+
+        // Until this run has spawned `unsquashfs`, or if it failed with
+        // `Busy`, the instance may be running the extraction of a clone,
+        // which is not ours to cancel.
+        while !thread.is_finished() {
+            if self.spawned.load(Ordering::SeqCst) {
+                self.unsquashfs.cancel().ok();
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        thread.join().ok();
+    }
+}
+
+impl Unsquashfs {
+    /// Starts extracting an image like [`Unsquashfs::extract_with`] on a
+    /// background thread, returning a handle to poll it from.
+    pub fn extract_start(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+    ) -> ExtractHandle {
+        self.start(archive.as_ref().to_path_buf(), options.clone(), |_| {})
+    }
+
+    /// Starts extracting an image like [`Unsquashfs::extract_start`], sending
+    /// [`ProgressEvent`]s like [`Unsquashfs::extract_with_events`] to the
+    /// returned receiver, for handing them to another thread.
+    ///
+    /// The channel holds up to `capacity` events. When it is full, progress
+    /// updates are dropped rather than buffered, since a newer one follows
+    /// anyway. The other events are always delivered, blocking the
+    /// extraction until there is room, so keep reading until the channel is
+    /// closed or drop the receiver.
+    pub fn extract_channel(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        capacity: usize,
+    ) -> (ExtractHandle, Receiver<ProgressEvent>) {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+
+        let handle = self.start(
+            archive.as_ref().to_path_buf(),
+            options.clone(),
+            move |event| {
+                if let ProgressEvent::Progress { .. } = event {
+                    tx.try_send(event).ok();
+                } else {
+                    tx.send(event).ok();
+                }
+            },
+        );
+
+        (handle, rx)
+    }
+
+    /// Extracts `archive` like [`Unsquashfs::extract_with_events`] on a
+    /// background thread, passing the events on to `forward` once the
+    /// handle has taken note of them.
+    fn start(
+        &self,
+        archive: PathBuf,
+        options: ExtractOptions,
+        mut forward: impl FnMut(ProgressEvent) + Send + 'static,
+    ) -> ExtractHandle {
+        let unsquashfs = self.clone();
+        let percent = Arc::new(AtomicU8::new(0));
+        let spawned = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let (percent, spawned) = (percent.clone(), spawned.clone());
+
+            thread::spawn(move || {
+                unsquashfs.extract_with_events(archive, &options, |event| {
+                    match event {
+                        ProgressEvent::Started => spawned.store(true, Ordering::SeqCst),
+                        ProgressEvent::Progress { percent: p } => {
+                            percent.store(p, Ordering::SeqCst)
+                        }
+                        _ => {}
+                    }
+
+                    forward(event);
+                })
+            })
+        };
+
+        ExtractHandle {
+            unsquashfs: self.clone(),
+            percent,
+            spawned,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use crate::{test::fake_binary, ExtractOptions, ProgressEvent, Status, Unsquashfs};
+
+    const SLOW: &str = "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10";
+
+    #[test]
+    fn test_extract_start() {
+        let binary = fake_binary(
+            "start",
+            "printf '[=====     ] 1/2  50%%\\r[==========] 2/2 100%%\\n'",
+        );
+
+        let handle = Unsquashfs::with_binary(binary)
+            .extract_start("testdata/test_extract.squashfs", &ExtractOptions::new());
+
+        while !handle.is_finished() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(handle.progress(), 100);
+        handle.wait().unwrap();
+    }
+
+    #[test]
+    fn test_drop_handle() {
+        let unsquashfs = Unsquashfs::with_binary(fake_binary("start-slow", SLOW));
+
+        let handle =
+            unsquashfs.extract_start("testdata/test_extract.squashfs", &ExtractOptions::new());
+        drop(handle);
+
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+    }
+
+    #[test]
+    fn test_drop_busy_handle() {
+        let unsquashfs = Unsquashfs::with_binary(fake_binary("start-busy", SLOW));
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Fails with `Busy`, and must not cancel the run of the clone.
+        let handle =
+            unsquashfs.extract_start("testdata/test_extract.squashfs", &ExtractOptions::new());
+        drop(handle);
+        assert_eq!(unsquashfs.status(), Status::Working { percent: 10 });
+
+        unsquashfs.cancel().unwrap();
+        t.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_extract_channel() {
+        let binary = fake_binary(
+            "channel",
+            "[ \"$1\" = -help ] && echo '-percentage' && exit 0
+for i in 10 20 30 40 50 60 70 80 90 100; do echo $i; done",
+        );
+
+        let (handle, events) = Unsquashfs::with_binary(binary).extract_channel(
+            "testdata/test_extract.squashfs",
+            &ExtractOptions::new(),
+            2,
+        );
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+
+            for event in events {
+                // Fall behind so that the channel fills up.
+                thread::sleep(Duration::from_millis(20));
+                received.push(event);
+            }
+
+            received
+        });
+
+        handle.wait().unwrap();
+        let received = reader.join().unwrap();
+
+        assert_eq!(received.first(), Some(&ProgressEvent::Started));
+        assert_eq!(received.last(), Some(&ProgressEvent::Finished));
+        assert!(received.len() <= 12);
+    }
+}
@@ -1,14 +1,18 @@
 use std::{
-    io::{self, BufReader, Error, ErrorKind, Read},
-    path::Path,
-    process::{ChildStdout, Stdio},
+    cell::RefCell,
+    ffi::OsString,
+    io::{self, BufRead, BufReader, Error, ErrorKind, Read},
+    ops::ControlFlow,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::{Path, PathBuf},
+    process::{self, Child, ChildStdout, ExitStatus, Stdio},
     str,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, RwLock,
+        Arc, Condvar, Mutex, OnceLock, RwLock,
     },
-    thread,
-    time::Duration,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use pty_process::{
@@ -17,43 +21,113 @@ use pty_process::{
 };
 use thiserror::Error;
 
-fn handle(stdout: ChildStdout, mut callback: impl FnMut(i32)) -> io::Result<()> {
-    let mut last_progress = 0;
-    let mut reader = BufReader::new(stdout);
+use cleanup::Snapshot;
+use logging::Span;
+use options::OnStderr;
+use progress::{handle, percent_only};
 
-    loop {
-        let mut data = [0; 0x1000];
-        let count = reader.read(&mut data)?;
+#[macro_use]
+mod logging;
 
-        if count == 0 {
-            return Ok(());
-        }
+mod atomic;
+mod backend;
+mod capability;
+mod cat;
+mod child;
+mod cleanup;
+mod create;
+mod dry_run;
+mod handle;
+mod list;
+mod many;
+mod options;
+mod progress;
+mod scratch;
+#[cfg(unix)]
+mod signal;
+mod stat;
+mod summary;
+#[cfg(feature = "tokio")]
+mod tokio_support;
+mod verify;
+mod version;
 
-        if let Ok(string) = str::from_utf8(&data[..count]) {
-            for line in string.split(['\r', '\n']) {
-                let len = line.len();
-                if line.starts_with('[') && line.ends_with('%') && len >= 4 {
-                    if let Ok(progress) = line[len - 4..len - 1].trim().parse::<i32>() {
-                        if last_progress != progress {
-                            callback(progress);
-                            last_progress = progress;
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
+pub use backend::Backend;
+pub use child::IoPriority;
+pub use create::CreateOptions;
+pub use dry_run::{DryRun, Usage};
+pub use handle::ExtractHandle;
+pub use list::{EntryKind, ListEntry};
+pub use options::{
+    ErrorMode, ExtractOptions, Threads, XattrPolicy, MAX_QUEUE_MB, MAX_THREADS, MIN_MEM_LIMIT,
+};
+pub use progress::{with_stats, Progress, ProgressEvent, ProgressStats, MIN_ESTIMATE_TIME};
+pub use stat::{Compression, SuperblockFlags, SuperblockInfo};
+pub use summary::ExtractSummary;
+pub use version::Version;
+
+/// Inherited variables removed from the environment of the tools, which
+/// would otherwise size the progress bar after the terminal of the caller
+/// instead of the pseudo-terminal it is read from, and break its parsing.
+const CLEARED_ENV: [&str; 2] = ["COLUMNS", "LINES"];
+
+/// How long [`Unsquashfs::cancel_blocking`] waits for the process to exit.
+pub const CANCEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a cancelled process has to exit after `SIGTERM` before it is
+/// killed, unless set with [`ExtractOptions::cancel_grace`].
+pub const DEFAULT_CANCEL_GRACE: Duration = Duration::from_secs(2);
 
+/// Runs `unsquashfs`, one thing at a time.
+///
+/// An instance can be reused for any number of runs one after the other,
+/// each starting afresh whatever the last one ended with. Clones share the
+/// status and cancellation of the instance they were cloned from, so that
+/// another thread can follow or cancel a run.
 #[derive(Clone)]
 pub struct Unsquashfs {
     cancel: Arc<AtomicBool>,
     status: Arc<RwLock<Status>>,
+    binary: Option<PathBuf>,
+    backend: Option<Backend>,
+    help: Arc<OnceLock<String>>,
+    version: Arc<OnceLock<Version>>,
+    /// Process ID of the running `unsquashfs`, which is also its process
+    /// group ID.
+    pid: Arc<Mutex<Option<u32>>>,
+    /// Whether cancelling the last run stopped a running process, notified
+    /// whenever a run ends.
+    ended: Arc<(Mutex<bool>, Condvar)>,
 }
 
+/// Lifecycle of the `unsquashfs` process spawned by an [`Unsquashfs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
+    /// Nothing has been run yet.
     Pending,
-    Working,
+    /// `unsquashfs` is running. `percent` is only updated by extractions.
+    Working { percent: i32 },
+    /// `unsquashfs` was stopped with [`Unsquashfs::pause`].
+    Paused { percent: i32 },
+    /// [`Unsquashfs::cancel`] was called and the process is being killed.
+    Cancelling,
+    /// The last run was cancelled.
+    Cancelled,
+    /// The last run exited successfully.
+    Done,
+    /// The last run exited with an error.
+    Failed,
+}
+
+impl Status {
+    /// Whether a run has started and not ended yet, including while it is
+    /// paused or being cancelled.
+    pub fn is_running(self) -> bool {
+        matches!(
+            self,
+            Status::Working { .. } | Status::Paused { .. } | Status::Cancelling
+        )
+    }
 }
 
 impl Default for Unsquashfs {
@@ -61,22 +135,543 @@ impl Default for Unsquashfs {
         Self {
             cancel: Arc::new(AtomicBool::new(false)),
             status: Arc::new(RwLock::new(Status::Pending)),
+            binary: None,
+            backend: None,
+            help: Arc::new(OnceLock::new()),
+            version: Arc::new(OnceLock::new()),
+            pid: Arc::new(Mutex::new(None)),
+            ended: Arc::new((Mutex::new(false), Condvar::new())),
         }
     }
 }
 
 #[derive(Debug, Error)]
 pub enum UnsquashfsError {
-    #[error("`unsquashfs` binary does not exist.")]
-    BinaryDoesNotExist,
+    #[error("{}", backend::not_found_message(.binary, .search_path, .fallbacks))]
+    BinaryDoesNotExist {
+        binary: PathBuf,
+        /// The `PATH` it was looked up in.
+        search_path: Option<OsString>,
+        /// The directories tried after `PATH`, none if `binary` is not a bare name.
+        fallbacks: Vec<PathBuf>,
+        #[source]
+        source: which::Error,
+    },
     #[error(transparent)]
     IO(#[from] io::Error),
     #[error(transparent)]
     Pty(#[from] pty_process::Error),
     #[error("`unsquashfs` is not start.")]
     Pending,
-    #[error("`unsquashfs` failed: {0}, output: {1}")]
-    Failure(io::Error, String),
+    #[error("`unsquashfs` was cancelled.")]
+    Cancelled,
+    /// `exit_code` is what `unsquashfs` exited with: 1 for a fatal error,
+    /// such as an image that can't be read or a destination that can't be
+    /// written, and 2 when it carried on past errors on some files. It is
+    /// `None` if the process didn't exit normally, such as when it was
+    /// killed by a signal, which is more likely to be worth retrying.
+    ///
+    /// `signal` is the signal that killed it then, such as `SIGKILL` from
+    /// the OOM killer or `SIGSEGV` from a broken binary. Both are `None` if
+    /// waiting for the process failed.
+    #[error("`unsquashfs` failed: {source}, output: {stderr}")]
+    Failure {
+        source: io::Error,
+        stderr: String,
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+    },
+    #[error("Invalid extract option: {0}")]
+    InvalidOption(String),
+    #[error("`{0}` is not supported.")]
+    Unsupported(String),
+    #[error("`{0}` is not supported by the installed `unsquashfs`.")]
+    UnsupportedFlag(String),
+    #[error("Failed to parse `unsquashfs` output: {0}")]
+    Parse(String),
+    #[error("`{}` does not exist in the archive.", .0.display())]
+    FileNotFound(PathBuf),
+    #[error("`{}` in the archive is not a regular file.", .0.display())]
+    NotAFile(PathBuf),
+    #[error("Archive `{}` does not exist.", .0.display())]
+    ArchiveNotFound(PathBuf),
+    /// `stderr` is the output of `unsquashfs`, `None` if this was found
+    /// before running it.
+    #[error("`{}` already exists in the destination.", .path.display())]
+    DestinationNotEmpty {
+        path: PathBuf,
+        stderr: Option<String>,
+    },
+    #[error("Cannot write to the destination `{}`.", .0.display())]
+    DestinationNotWritable(PathBuf),
+    /// `bytes_written_estimate` guesses how much was extracted before the
+    /// device filled up, from the percentage reached and the size of the
+    /// image. The image is compressed, so more was likely written. It is
+    /// `None` if no progress was reported. `stderr` is the output of
+    /// `unsquashfs`, `None` if this was found before running it.
+    #[error("No space left on the device of `{}`.", .destination.display())]
+    DiskFull {
+        destination: PathBuf,
+        bytes_written_estimate: Option<u64>,
+        stderr: Option<String>,
+    },
+    #[error("Cannot rename into `{}`, which is on another filesystem.", .0.display())]
+    CrossDevice(PathBuf),
+    /// `path` is what couldn't be accessed, if it is known, such as a file
+    /// `unsquashfs` was not allowed to create or a binary that is not
+    /// executable. `stderr` is the output of `unsquashfs` if it was the one
+    /// denied.
+    #[error("Permission denied{}", .path.as_ref().map(|path| format!(" for `{}`", path.display())).unwrap_or_default())]
+    PermissionDenied {
+        path: Option<PathBuf>,
+        stderr: Option<String>,
+    },
+    #[error("Not allowed to move `unsquashfs` into the cgroup `{}`.", .0.display())]
+    CgroupDenied(PathBuf),
+    #[error("`unsquashfs` made no progress for {0:?}.")]
+    Timeout(Duration),
+    #[error("`unsquashfs` was killed after running for {elapsed:?}, at {percent}%.")]
+    TimedOut { elapsed: Duration, percent: i32 },
+    #[error("`unsquashfs` did not exit within {0:?} of being cancelled.")]
+    CancelTimeout(Duration),
+    #[error("Cannot do this while `unsquashfs` is {0:?}.")]
+    InvalidStatus(Status),
+    #[error("Another run of this instance is still in progress.")]
+    Busy,
+    #[error("Failed to clean up the cancelled extraction: {0}")]
+    Cleanup(io::Error),
+    /// The image has no squashfs superblock or `unsquashfs` found it
+    /// corrupted. `path` is the file inside the image whose data couldn't
+    /// be read, if it is known.
+    #[error("The image is corrupted{}, output: {stderr}", .path.as_ref().map(|path| format!(" in `{}`", path.display())).unwrap_or_default())]
+    Corrupted {
+        path: Option<PathBuf>,
+        stderr: String,
+    },
+    /// `available` are the decompressors the installed `unsquashfs` lists,
+    /// and `stderr` is empty if this was found before extracting, see
+    /// [`ExtractOptions::check_compression`].
+    #[error(
+        "The image uses {compression} compression, which the installed `unsquashfs` does not support{}.",
+        available_list(.available)
+    )]
+    UnsupportedCompression {
+        compression: String,
+        available: Vec<String>,
+        stderr: String,
+    },
+    /// `path` is where `unsquashfs` failed to create a file, as it printed
+    /// it.
+    #[error("Failed to write `{}`, output: {stderr}", .path.display())]
+    WriteFailed { path: PathBuf, stderr: String },
+    #[error("Progress callback failed: {0}")]
+    Callback(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Cancels the running extraction through [`Unsquashfs::cancel`] if it is
+/// not reset for the stall timeout, or once it has run for the time limit.
+///
+/// Dropping it stops watching, so that a run that failed or panicked can't
+/// leave it behind to cancel a later run of the same instance.
+struct Watchdog {
+    last_progress: Arc<Mutex<Instant>>,
+    fired: Arc<Mutex<Option<UnsquashfsError>>>,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Starts watching if `options` has a timeout or a time limit.
+    fn spawn(unsquashfs: &Unsquashfs, options: &ExtractOptions) -> Option<Self> {
+        let (timeout, time_limit) = (options.timeout, options.time_limit);
+
+        if timeout.is_none() && time_limit.is_none() {
+            return None;
+        }
+
+        let start = Instant::now();
+        let last_progress = Arc::new(Mutex::new(start));
+        let fired = Arc::new(Mutex::new(None));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let (unsquashfs, last_progress) = (unsquashfs.clone(), last_progress.clone());
+            let (fired, done) = (fired.clone(), done.clone());
+            let span = Span::current();
+
+            thread::spawn(move || {
+                let _entered = span.enter();
+                let deadline = time_limit.map(|limit| start + limit);
+
+                while !done.load(Ordering::SeqCst) {
+                    let status = unsquashfs.status();
+
+                    // A paused extraction is not stuck.
+                    if matches!(status, Status::Paused { .. }) {
+                        *last_progress.lock().unwrap() = Instant::now();
+                    }
+
+                    let now = Instant::now();
+
+                    let err = match (deadline, timeout) {
+                        (Some(deadline), _) if now >= deadline => Some(UnsquashfsError::TimedOut {
+                            elapsed: now - start,
+                            percent: match status {
+                                Status::Working { percent } | Status::Paused { percent } => percent,
+                                _ => 0,
+                            },
+                        }),
+                        (_, Some(timeout)) if *last_progress.lock().unwrap() + timeout <= now => {
+                            Some(UnsquashfsError::Timeout(timeout))
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(err) = err {
+                        if unsquashfs.cancel().is_ok() {
+                            *fired.lock().unwrap() = Some(err);
+                        }
+
+                        return;
+                    }
+
+                    // Wake up right at the deadline rather than up to a
+                    // polling interval after it.
+                    let interval = Duration::from_millis(10);
+                    thread::sleep(deadline.map_or(interval, |deadline| {
+                        interval.min(deadline.saturating_duration_since(now))
+                    }));
+                }
+            })
+        };
+
+        Some(Self {
+            last_progress,
+            fired,
+            done,
+            thread: Some(thread),
+        })
+    }
+
+    fn reset(&self) {
+        *self.last_progress.lock().unwrap() = Instant::now();
+    }
+
+    /// Stops the watchdog, returning the error to fail with if it cancelled
+    /// the extraction.
+    fn finish(mut self) -> Option<UnsquashfsError> {
+        self.stop();
+        self.fired.lock().unwrap().take()
+    }
+
+    fn stop(&mut self) {
+        self.done.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A run claimed with [`Unsquashfs::begin`], until its process is handed to
+/// [`Unsquashfs::control`].
+///
+/// Dropping it before then, when something fails before the process is
+/// spawned, ends the run with the status the instance had before.
+struct Claim<'a> {
+    unsquashfs: &'a Unsquashfs,
+    previous: Option<Status>,
+}
+
+impl Claim<'_> {
+    /// Hands the run over to whatever now waits for its process.
+    fn spawned(mut self) {
+        self.previous = None;
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        let Some(previous) = self.previous else {
+            return;
+        };
+
+        let status = match self.unsquashfs.cancel.swap(false, Ordering::SeqCst) {
+            true => Status::Cancelled,
+            false => previous,
+        };
+        self.unsquashfs.end(status, false);
+    }
+}
+
+/// The thread waiting for a child spawned by [`Unsquashfs::control`].
+///
+/// Dropping it without joining, such as when a callback panics or an error
+/// is returned early, cancels the child and waits for it to be reaped, so
+/// that it doesn't keep running with nobody reading its output.
+struct Control {
+    unsquashfs: Unsquashfs,
+    thread: Option<JoinHandle<io::Result<Option<ExitStatus>>>>,
+}
+
+impl Control {
+    /// Waits for the child to exit, returning `None` if it was cancelled.
+    fn join(mut self) -> io::Result<Option<ExitStatus>> {
+        self.thread.take().unwrap().join().unwrap()
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+
+        diag!(
+            warn,
+            "stopping `unsquashfs` as nothing waits for it anymore"
+        );
+        self.unsquashfs.cancel().ok();
+        thread.join().ok();
+    }
+}
+
+/// Builds the error for an extraction into `dest` that exited with `status`,
+/// recognizing files that already existed when overwriting was disabled and
+/// running out of space, after writing about `written` bytes.
+fn extraction_failed(
+    status: ExitStatus,
+    stderr: String,
+    dest: Option<&Path>,
+    written: Option<u64>,
+) -> UnsquashfsError {
+    diag!(error, "archive extraction failed with {status}");
+
+    let create_failure = create_failure(&stderr);
+
+    if let Some((path, "File exists")) = create_failure {
+        return UnsquashfsError::DestinationNotEmpty {
+            path,
+            stderr: Some(stderr),
+        };
+    }
+
+    if let Some(dest) = dest.filter(|_| stderr.contains("No space left on device")) {
+        return UnsquashfsError::DiskFull {
+            destination: dest.to_path_buf(),
+            bytes_written_estimate: written,
+            stderr: Some(stderr),
+        };
+    }
+
+    if let Some(path) = denied_path(&stderr) {
+        return UnsquashfsError::PermissionDenied {
+            path,
+            stderr: Some(stderr),
+        };
+    }
+
+    if let Some((path, _)) = create_failure {
+        return UnsquashfsError::WriteFailed { path, stderr };
+    }
+
+    if let Some(compression) = unsupported_compression(&stderr) {
+        return UnsquashfsError::UnsupportedCompression {
+            compression,
+            available: decompressors(&stderr),
+            stderr,
+        };
+    }
+
+    if stderr.contains("Can't find a SQUASHFS superblock")
+        || stderr.to_ascii_lowercase().contains("corrupted")
+    {
+        return UnsquashfsError::Corrupted { path: None, stderr };
+    }
+
+    UnsquashfsError::Failure {
+        source: Error::other(format!("archive extraction failed with {status}")),
+        stderr,
+        exit_code: status.code(),
+        signal: status.signal(),
+    }
+}
+
+/// Makes the path of an image absolute, failing with
+/// [`UnsquashfsError::ArchiveNotFound`] if there is nothing there.
+fn resolve_archive(archive: &Path) -> Result<PathBuf, UnsquashfsError> {
+    archive.canonicalize().map_err(|e| match e.kind() {
+        ErrorKind::NotFound => UnsquashfsError::ArchiveNotFound(archive.to_path_buf()),
+        _ => denied(Some(archive))(e),
+    })
+}
+
+/// Maps an error accessing `path` to [`UnsquashfsError::PermissionDenied`]
+/// if it was not allowed to.
+fn denied(path: Option<&Path>) -> impl FnOnce(io::Error) -> UnsquashfsError + '_ {
+    move |e| match e.kind() {
+        ErrorKind::PermissionDenied => UnsquashfsError::PermissionDenied {
+            path: path.map(Path::to_path_buf),
+            stderr: None,
+        },
+        _ => e.into(),
+    }
+}
+
+/// Maps a failure to spawn `binary` in a pty like [`denied`].
+fn spawn_denied(binary: Option<&Path>) -> impl FnOnce(pty_process::Error) -> UnsquashfsError + '_ {
+    move |e| match e {
+        pty_process::Error::Io(e) => denied(binary)(e),
+        e => e.into(),
+    }
+}
+
+/// Asks `child` to exit with `SIGTERM`, so that it can close the files it is
+/// writing, and kills it if it is still running after `grace`. The child is
+/// always reaped.
+fn stop(child: &mut Child, grace: Duration) -> io::Result<ExitStatus> {
+    #[cfg(unix)]
+    if !grace.is_zero() {
+        signal::terminate(child.id());
+
+        let start = Instant::now();
+
+        while start.elapsed() < grace {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        diag!(
+            warn,
+            "`unsquashfs` ignored SIGTERM for {grace:?}, killing it"
+        );
+    }
+
+    child.kill()?;
+    child.wait()
+}
+
+/// Reads all of `stderr`, passing each line to `on_stderr` as it arrives.
+fn read_stderr(stderr: impl Read, on_stderr: Option<OnStderr>) -> String {
+    let mut stderr = BufReader::new(stderr);
+    let mut buf = String::new();
+    let mut line = Vec::new();
+
+    while let Ok(1..) = stderr.read_until(b'\n', &mut line) {
+        let text = String::from_utf8_lossy(&line);
+
+        if let Some(on_stderr) = &on_stderr {
+            on_stderr.call(text.trim_end_matches(['\r', '\n']));
+        }
+
+        buf.push_str(&text);
+        line.clear();
+    }
+
+    buf
+}
+
+/// Guesses how many bytes an extraction of `archive` wrote by the time it
+/// reached `percent`, as that share of the image.
+fn written_estimate(archive: &Path, options: &ExtractOptions, percent: i32) -> Option<u64> {
+    if percent <= 0 {
+        return None;
+    }
+
+    let size = archive
+        .metadata()
+        .ok()?
+        .len()
+        .saturating_sub(options.offset.unwrap_or(0));
+
+    Some(size / 100 * percent.min(100) as u64)
+}
+
+/// Finds the path and the reason in errors such as
+/// `write_file: failed to create file dest/a, because File exists`.
+fn create_failure(stderr: &str) -> Option<(PathBuf, &str)> {
+    stderr.lines().find_map(|line| {
+        let (_, rest) = line
+            .split_once("failed to create file ")
+            .or_else(|| line.split_once("failed to make directory "))?;
+        let (path, reason) = rest.rsplit_once(", because ")?;
+
+        Some((PathBuf::from(path), reason.trim()))
+    })
+}
+
+/// Finds what `unsquashfs` was not allowed to create, from errors such as
+/// `create_inode: could not create character device dest/dev/null, because
+/// you're not superuser!`, if it says.
+fn denied_path(stderr: &str) -> Option<Option<PathBuf>> {
+    const OBJECTS: [&str; 5] = [" file ", " directory ", " device ", " symlink ", " on "];
+
+    stderr.lines().find_map(|line| {
+        let (head, reason) = line.rsplit_once(" because ")?;
+
+        if ![
+            "Permission denied",
+            "Operation not permitted",
+            "not superuser",
+        ]
+        .iter()
+        .any(|denial| reason.contains(denial))
+        {
+            return None;
+        }
+
+        let head = head.trim_end_matches(',');
+        let path = OBJECTS
+            .iter()
+            .find_map(|object| head.split_once(object))
+            .map(|(_, path)| PathBuf::from(path));
+
+        Some(path)
+    })
+}
+
+/// Finds the compression from a line such as `Filesystem uses lzo
+/// compression, this is unsupported by this version`.
+fn unsupported_compression(stderr: &str) -> Option<String> {
+    stderr.lines().find_map(|line| {
+        if !line.contains("unsupported") && !line.contains("not supported") {
+            return None;
+        }
+
+        let (_, rest) = line.split_once("uses ")?;
+        let (compression, _) = rest.split_once(" compression")?;
+
+        Some(compression.to_string())
+    })
+}
+
+/// The names under `Decompressors available:`, which `unsquashfs` prints at
+/// the end of `-help` and when it can't decompress an image, one per
+/// indented line.
+fn decompressors(text: &str) -> Vec<String> {
+    text.lines()
+        .skip_while(|line| !line.contains("Decompressors available"))
+        .skip(1)
+        .take_while(|line| line.starts_with(char::is_whitespace) && !line.trim().is_empty())
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+fn available_list(available: &[String]) -> String {
+    match available.is_empty() {
+        true => String::new(),
+        false => format!(", only {}", available.join(", ")),
+    }
 }
 
 impl Unsquashfs {
@@ -84,17 +679,144 @@ impl Unsquashfs {
         Unsquashfs::default()
     }
 
+    /// Uses the given `unsquashfs` executable instead of looking it up in `PATH`.
+    ///
+    /// The path must point to an executable file, otherwise running anything
+    /// returns [`UnsquashfsError::BinaryDoesNotExist`].
+    pub fn with_binary(path: impl Into<PathBuf>) -> Self {
+        Self {
+            binary: Some(path.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Always uses the given [`Backend`] instead of detecting the installed one.
+    pub fn with_backend(backend: Backend) -> Self {
+        Self {
+            backend: Some(backend),
+            ..Default::default()
+        }
+    }
+
+    /// Resolves the executable to spawn and the backend it implements.
+    fn binary(&self) -> Result<(PathBuf, Backend), UnsquashfsError> {
+        backend::resolve(self.binary.as_deref(), self.backend)
+    }
+
+    /// Returns the state of the current or last run, which can be polled
+    /// from another thread.
+    pub fn status(&self) -> Status {
+        *self.status.read().unwrap()
+    }
+
+    /// Whether something is running right now, see [`Status::is_running`].
+    pub fn is_working(&self) -> bool {
+        self.status().is_running()
+    }
+
     pub fn cancel(&self) -> Result<(), UnsquashfsError> {
-        match *self.status.read().unwrap() {
-            Status::Pending => Err(UnsquashfsError::Pending),
-            Status::Working => {
+        let mut status = self.status.write().unwrap();
+
+        match *status {
+            Status::Working { .. } | Status::Paused { .. } => {
+                *status = Status::Cancelling;
                 self.cancel.store(true, Ordering::SeqCst);
                 Ok(())
             }
+            Status::Cancelling => Ok(()),
+            Status::Pending | Status::Cancelled | Status::Done | Status::Failed => {
+                Err(UnsquashfsError::Pending)
+            }
+        }
+    }
+
+    /// Cancels the running process like [`Unsquashfs::cancel`], then waits
+    /// for it to be killed and reaped, so that nothing writes to the
+    /// destination anymore once this returns.
+    ///
+    /// Fails with [`UnsquashfsError::CancelTimeout`] if that takes longer
+    /// than [`CANCEL_TIMEOUT`].
+    pub fn cancel_blocking(&self) -> Result<(), UnsquashfsError> {
+        self.cancel_and_wait(Some(CANCEL_TIMEOUT)).map(|_| ())
+    }
+
+    /// Cancels the running process like [`Unsquashfs::cancel_blocking`],
+    /// waiting for up to `timeout`, or for as long as it takes with `None`.
+    ///
+    /// Returns whether the process was still running when it was stopped,
+    /// rather than having exited on its own in the meantime.
+    pub fn cancel_and_wait(&self, timeout: Option<Duration>) -> Result<bool, UnsquashfsError> {
+        let (stopped, ended) = &*self.ended;
+        // Locked before cancelling so that the end of the run can't be
+        // notified before we wait for it.
+        let stopped = stopped.lock().unwrap();
+
+        self.cancel()?;
+
+        let running = |_: &mut bool| self.is_working();
+        let stopped = match timeout {
+            Some(timeout) => {
+                let (stopped, wait) = ended.wait_timeout_while(stopped, timeout, running).unwrap();
+
+                if wait.timed_out() {
+                    return Err(UnsquashfsError::CancelTimeout(timeout));
+                }
+
+                stopped
+            }
+            None => ended.wait_while(stopped, running).unwrap(),
+        };
+
+        Ok(*stopped)
+    }
+
+    /// Marks the instance as working for a new run, failing with
+    /// [`UnsquashfsError::Busy`] if another one is in progress, such as on a
+    /// clone used by another thread.
+    fn begin(&self) -> Result<Claim<'_>, UnsquashfsError> {
+        let mut status = self.status.write().unwrap();
+
+        if status.is_running() {
+            return Err(UnsquashfsError::Busy);
+        }
+
+        let previous = std::mem::replace(&mut *status, Status::Working { percent: 0 });
+        // Left over if the last run ended right as it was cancelled.
+        self.cancel.store(false, Ordering::SeqCst);
+
+        Ok(Claim {
+            unsquashfs: self,
+            previous: Some(previous),
+        })
+    }
+
+    /// Records how the run ended, `stopped` telling whether it was cancelled
+    /// while the process was running, and wakes up
+    /// [`Unsquashfs::cancel_and_wait`].
+    fn end(&self, status: Status, stopped: bool) {
+        let (lock, ended) = &*self.ended;
+        let mut guard = lock.lock().unwrap();
+
+        *self.pid.lock().unwrap() = None;
+        *self.status.write().unwrap() = status;
+        *guard = stopped;
+        ended.notify_all();
+    }
+
+    /// Records the percentage of a running extraction in [`Status::Working`].
+    fn set_percent(&self, progress: Progress) {
+        if let Status::Working { percent } = &mut *self.status.write().unwrap() {
+            *percent = progress.percent;
         }
     }
 
     /// Extracts an image using either unsquashfs.
+    ///
+    /// `callback` is any closure taking the percentage. It is called on the
+    /// calling thread as the extraction goes, so it needs neither `Send` nor
+    /// `'static`, and can borrow from the caller. A callback shared with
+    /// other threads can be held in an `Arc` and called from a closure, and
+    /// [`Unsquashfs::extract_start`] extracts on a thread of its own.
     pub fn extract(
         &self,
         archive: impl AsRef<Path>,
@@ -102,124 +824,644 @@ impl Unsquashfs {
         thread: Option<usize>,
         callback: impl FnMut(i32),
     ) -> Result<(), UnsquashfsError> {
-        if which::which("unsquashfs").is_err() {
-            return Err(UnsquashfsError::BinaryDoesNotExist);
-        }
+        let mut options = ExtractOptions::new().dest(directory);
 
-        let archive = archive.as_ref().canonicalize()?;
-        let directory = directory.as_ref().canonicalize()?;
-
-        let directory = directory
-            .to_str()
-            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid directory path"))?
-            .replace('\'', "'\"'\"'");
+        if let Some(limit_thread) = thread {
+            options = options.threads(limit_thread);
+        }
 
-        let archive = archive
-            .to_str()
-            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid archive path"))?
-            .replace('\'', "'\"'\"'");
+        self.extract_with(archive, &options, callback)
+    }
 
-        let pty = Pty::new()?;
-        pty.resize(Size::new(30, 80))?;
+    /// Extracts only the given paths inside an image, such as `/boot`.
+    ///
+    /// Leading slashes are optional. `paths` must not be empty, as that would
+    /// extract the whole image.
+    pub fn extract_paths(
+        &self,
+        archive: impl AsRef<Path>,
+        directory: impl AsRef<Path>,
+        paths: &[impl AsRef<Path>],
+        thread: Option<usize>,
+        callback: impl FnMut(i32),
+    ) -> Result<(), UnsquashfsError> {
+        if paths.is_empty() {
+            return Err(UnsquashfsError::InvalidOption(
+                "no paths to extract given".to_string(),
+            ));
+        }
 
-        let mut command = Command::new("unsquashfs");
+        let mut options = ExtractOptions::new().dest(directory).paths(paths);
 
         if let Some(limit_thread) = thread {
-            command.arg("-p").arg(limit_thread.to_string());
+            options = options.threads(limit_thread);
         }
 
-        command
-            .arg("-f")
-            .arg("-q")
-            .arg("-d")
-            .arg(directory)
-            .arg(archive);
+        self.extract_with(archive, &options, callback)
+    }
 
-        let mut child = command
-            .env("COLUMNS", "")
-            .env("LINES", "")
-            .env("TERM", "xterm-256color")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn(&pty.pts()?)?;
+    /// Extracts an image using the given [`ExtractOptions`].
+    ///
+    /// The options are validated before `unsquashfs` is spawned, so the same
+    /// options can be reused for several images. An instance runs one thing
+    /// at a time, so this fails with [`UnsquashfsError::Busy`] while a clone
+    /// is still running something else.
+    pub fn extract_with(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        callback: impl FnMut(i32),
+    ) -> Result<(), UnsquashfsError> {
+        let mut options = options.clone();
+        options.percentage = true;
 
-        *self.status.write().unwrap() = Status::Working;
+        self.extract_inner(
+            archive.as_ref(),
+            &options,
+            || {},
+            percent_only(callback),
+            |_| {},
+        )
+        .map(|_| ())
+    }
 
-        let cc = self.cancel.clone();
-        let status_clone = self.status.clone();
+    /// Extracts an image like [`Unsquashfs::extract_with`] without reporting
+    /// progress, for batch extractions that don't show it.
+    ///
+    /// `-no-progress` is passed if the installed `unsquashfs` supports it,
+    /// and its output is discarded without being parsed. Cancelling and
+    /// errors work as for the other variants, but [`ExtractOptions::timeout`]
+    /// can't tell when no progress is made and is rejected.
+    pub fn extract_no_progress(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+    ) -> Result<(), UnsquashfsError> {
+        let mut options = options.clone();
+        options.no_progress = true;
 
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Failed to get stdout"))?;
+        self.extract_inner(archive.as_ref(), &options, || {}, |_| {}, |_| {})
+            .map(|_| ())
+    }
 
-        let stderr = child
-            .stderr
-            .take()
-            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Failed to get stderr"))?;
+    /// Extracts an image like [`Unsquashfs::extract_with`], reporting the
+    /// inode counters alongside the percentage.
+    pub fn extract_with_progress(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        callback: impl FnMut(Progress),
+    ) -> Result<(), UnsquashfsError> {
+        self.extract_inner(archive.as_ref(), options, || {}, callback, |_| {})
+            .map(|_| ())
+    }
 
-        let process_control = thread::spawn(move || -> io::Result<()> {
-            loop {
-                let wait = child.try_wait()?;
+    /// Extracts an image like [`Unsquashfs::extract_with`], reporting when
+    /// `unsquashfs` starts and how it ended as [`ProgressEvent`]s.
+    ///
+    /// On success, the last event is either [`ProgressEvent::Finished`] or
+    /// [`ProgressEvent::Cancelled`].
+    pub fn extract_with_events(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        callback: impl FnMut(ProgressEvent),
+    ) -> Result<(), UnsquashfsError> {
+        let callback = RefCell::new(callback);
+        let emit = |event| (callback.borrow_mut())(event);
 
-                if cc.load(Ordering::SeqCst) {
-                    child.kill()?;
-                    cc.store(false, Ordering::SeqCst);
-                    *status_clone.write().unwrap() = Status::Pending;
-                    return Ok(());
-                }
+        let mut options = options.clone();
+        options.percentage = true;
 
-                let Some(wait) = wait else {
-                    thread::sleep(Duration::from_millis(10));
-                    continue;
-                };
+        self.extract_inner(
+            archive.as_ref(),
+            &options,
+            || emit(ProgressEvent::Started),
+            percent_only(|percent| {
+                emit(ProgressEvent::Progress {
+                    percent: percent.clamp(0, 100) as u8,
+                })
+            }),
+            |_| {},
+        )?;
 
-                *status_clone.write().unwrap() = Status::Pending;
-
-                if !wait.success() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!(
-                            "archive extraction failed with status: {}",
-                            wait.code().unwrap_or(1),
-                        ),
-                    ));
-                } else {
-                    return Ok(());
-                }
-            }
+        emit(match self.status() {
+            Status::Cancelled => ProgressEvent::Cancelled,
+            _ => ProgressEvent::Finished,
         });
 
-        handle(stdout, callback)?;
+        Ok(())
+    }
 
-        let mut stderr = BufReader::new(stderr);
-        let mut buf = String::new();
-        stderr.read_to_string(&mut buf).ok();
+    /// Extracts an image like [`Unsquashfs::extract_with_progress`], stopping
+    /// it when `callback` returns [`ControlFlow::Break`].
+    ///
+    /// Breaking kills `unsquashfs` like [`Unsquashfs::cancel`] and returns
+    /// [`UnsquashfsError::Cancelled`].
+    pub fn extract_controlled(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        mut callback: impl FnMut(Progress) -> ControlFlow<()>,
+    ) -> Result<(), UnsquashfsError> {
+        let mut stopped = false;
 
-        process_control
-            .join()
-            .unwrap()
-            .map_err(|e| UnsquashfsError::Failure(e, buf))?;
+        self.extract_inner(
+            archive.as_ref(),
+            options,
+            || {},
+            |progress| {
+                if !stopped && callback(progress).is_break() {
+                    stopped = self.cancel().is_ok();
+                }
+            },
+            |_| {},
+        )?;
 
-        Ok(())
+        match stopped && self.status() == Status::Cancelled {
+            true => Err(UnsquashfsError::Cancelled),
+            false => Ok(()),
+        }
     }
-}
 
-#[cfg(test)]
-pub mod test {
-    use std::{env::temp_dir, fs, thread, time::Duration};
+    /// Extracts an image like [`Unsquashfs::extract_with`], stopping it when
+    /// `callback` fails.
+    ///
+    /// The error kills `unsquashfs` like [`Unsquashfs::cancel`] and is
+    /// returned as [`UnsquashfsError::Callback`].
+    pub fn extract_try<E>(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        mut callback: impl FnMut(i32) -> Result<(), E>,
+    ) -> Result<(), UnsquashfsError>
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let mut error = None;
+        let mut last_percent = 0;
 
-    use crate::Unsquashfs;
+        let result = self.extract_controlled(archive, options, |progress| {
+            if progress.percent == last_percent {
+                return ControlFlow::Continue(());
+            }
 
-    #[test]
-    fn test_extract() {
-        let unsquashfs = Unsquashfs::default();
-        let unsquashfs_clone = unsquashfs.clone();
+            last_percent = progress.percent;
 
-        let t = thread::spawn(move || {
-            let output = temp_dir().join("unsqfs-wrap-test-extract");
-            fs::create_dir_all(&output).unwrap();
-            unsquashfs
+            match callback(progress.percent) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(e) => {
+                    error = Some(e);
+                    ControlFlow::Break(())
+                }
+            }
+        });
+
+        match (result, error) {
+            (Err(UnsquashfsError::Cancelled) | Ok(()), Some(e)) => {
+                Err(UnsquashfsError::Callback(e.into()))
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Extracts an image like [`Unsquashfs::extract_with_progress`], also
+    /// calling `on_file` with the path inside the image of each file as it is
+    /// written, such as `/etc/os-release` (`-info`).
+    ///
+    /// `-q` is not passed even if [`ExtractOptions::quiet`] is set, as it
+    /// hides the file names.
+    pub fn extract_with_files(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        callback: impl FnMut(Progress),
+        on_file: impl FnMut(&Path),
+    ) -> Result<(), UnsquashfsError> {
+        let mut options = options.clone().quiet(false);
+        options.info = true;
+
+        self.extract_inner(archive.as_ref(), &options, || {}, callback, on_file)
+            .map(|_| ())
+    }
+
+    /// Extracts an image like [`Unsquashfs::extract_with`], returning what
+    /// was written.
+    ///
+    /// `-q` is not passed even if [`ExtractOptions::quiet`] is set, as it
+    /// hides the summary the counts are read from. The summary is empty if
+    /// the extraction was cancelled.
+    pub fn extract_stats(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        callback: impl FnMut(i32),
+    ) -> Result<ExtractSummary, UnsquashfsError> {
+        let mut options = options.clone().quiet(false);
+        options.percentage = true;
+
+        let mut summary = self.extract_inner(
+            archive.as_ref(),
+            &options,
+            || {},
+            percent_only(callback),
+            |_| {},
+        )?;
+
+        if self.status() == Status::Done {
+            summary.count_bytes(&options);
+        }
+
+        Ok(summary)
+    }
+
+    /// Extracts an image, calling `on_start` once `unsquashfs` is spawned.
+    fn extract_inner(
+        &self,
+        archive: &Path,
+        options: &ExtractOptions,
+        on_start: impl FnOnce(),
+        callback: impl FnMut(Progress),
+        mut on_file: impl FnMut(&Path),
+    ) -> Result<ExtractSummary, UnsquashfsError> {
+        let span = Span::extract(archive, options.dest_dir());
+        let _entered = span.enter();
+
+        options.validate()?;
+        let mut callback = progress::stepped(options.progress_step, callback);
+        self.preflight(archive, options)?;
+
+        let claim = self.begin()?;
+        let (binary, backend) = self.binary()?;
+        // Even if `-no-progress` is not supported and dropped, unless the
+        // files are still wanted.
+        let drain = options.no_progress && !options.info;
+
+        let options = match backend {
+            Backend::Unsquashfs => self.degrade(options)?,
+            Backend::Rdsquashfs => options.clone(),
+        };
+        let args = options.args(archive, backend)?;
+
+        if backend == Backend::Unsquashfs {
+            self.require_flags(&options.required_flags())?;
+        }
+
+        options::check_dest(options.dest_dir())?;
+
+        let snapshot = match options.cleanup_on_cancel {
+            true => Some(Snapshot::take(options.dest_dir()).map_err(UnsquashfsError::Cleanup)?),
+            false => None,
+        };
+
+        let pty = Pty::new()?;
+        pty.resize(Size::new(30, 80))?;
+
+        diag!(debug, "spawning {}", logging::command_line(&binary, &args));
+
+        let mut command = Command::new(&binary);
+        command.args(args);
+
+        for key in CLEARED_ENV {
+            command.env_remove(key);
+        }
+        command.envs(&options.env);
+
+        // Lowering the priority may be what is not allowed, rather than
+        // running the binary.
+        let pre_exec = child::pre_exec(&options)?;
+        let blame = pre_exec.is_none().then_some(binary.as_path());
+
+        if let Some(pre_exec) = pre_exec {
+            // SAFETY: it only makes system calls, which is fine after fork.
+            unsafe { command.pre_exec(pre_exec) };
+        }
+
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn(&pty.pts()?)
+            .map_err(spawn_denied(blame))?;
+
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Failed to get stdout"))?;
+
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Failed to get stderr"))?;
+
+        let process_control = self.control(claim, child, options.error_mode, options.cancel_grace);
+        let on_stderr = options.on_stderr.clone();
+        let stderr = thread::spawn(move || read_stderr(stderr, on_stderr));
+        on_start();
+        let watchdog = Watchdog::spawn(self, &options);
+
+        let output = options.output()?;
+        let on_file = |path: &Path| {
+            if let Some(on_file) = &options.on_file {
+                on_file.call(path);
+            }
+
+            on_file(path);
+        };
+
+        let mut last_progress = Progress::default();
+        let on_progress = |progress: Progress| {
+            if let Some(watchdog) = &watchdog {
+                watchdog.reset();
+            }
+
+            span.record_percent(progress.percent);
+            self.set_percent(progress);
+            last_progress = progress;
+            callback(progress);
+        };
+        let mut summary = match drain {
+            true => {
+                io::copy(&mut stdout, &mut io::sink())?;
+                ExtractSummary::default()
+            }
+            false => handle(stdout, output, on_progress, on_file)?,
+        };
+
+        let buf = options.filter_stderr(stderr.join().unwrap());
+        logging::stderr(&buf);
+
+        let wait = match process_control.join() {
+            Ok(wait) => wait,
+            Err(source) => {
+                return Err(UnsquashfsError::Failure {
+                    source,
+                    stderr: buf,
+                    exit_code: None,
+                    signal: None,
+                })
+            }
+        };
+
+        let timed_out = watchdog.and_then(Watchdog::finish);
+
+        if wait.is_none() {
+            if let Some(snapshot) = snapshot {
+                snapshot.remove_new().map_err(UnsquashfsError::Cleanup)?;
+            }
+        }
+
+        if let Some(err) = timed_out {
+            return Err(err);
+        }
+
+        match wait {
+            Some(wait) if !options.error_mode.succeeded(wait) => Err(extraction_failed(
+                wait,
+                buf,
+                Some(options.dest_dir()),
+                written_estimate(archive, &options, last_progress.percent),
+            )),
+            Some(_) => {
+                if options.error_mode == ErrorMode::Ignore {
+                    summary.errors(&buf);
+                }
+
+                // The last update may be missing from the output, or the
+                // backend may not report progress at all.
+                if let Some(progress) = last_progress.completed() {
+                    callback(progress);
+                }
+
+                // Not every `unsquashfs` prints the count it uses.
+                summary.processors = summary.processors.or(options.threads.map(Threads::count));
+                span.record_summary(&summary);
+
+                Ok(summary)
+            }
+            None => Ok(ExtractSummary::default()),
+        }
+    }
+
+    /// Spawns the thread that waits for `child` of the run claimed by
+    /// `claim`, stopping it with [`stop`] if [`Unsquashfs::cancel`] is
+    /// called.
+    fn control(
+        &self,
+        claim: Claim,
+        mut child: Child,
+        error_mode: ErrorMode,
+        grace: Duration,
+    ) -> Control {
+        *self.pid.lock().unwrap() = Some(child.id());
+        claim.spawned();
+
+        let cc = self.cancel.clone();
+        let unsquashfs = self.clone();
+        let span = Span::current();
+
+        let thread = thread::spawn(move || {
+            let _entered = span.enter();
+            let mut stopped = false;
+
+            let wait = loop {
+                let wait = match child.try_wait() {
+                    Ok(wait) => wait,
+                    Err(e) => break Err(e),
+                };
+
+                if cc.load(Ordering::SeqCst) {
+                    cc.store(false, Ordering::SeqCst);
+                    stopped = wait.is_none();
+                    break stop(&mut child, grace).map(|_| None);
+                }
+
+                match wait {
+                    Some(wait) => break Ok(Some(wait)),
+                    None => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+
+            let status = match wait {
+                Ok(None) => Status::Cancelled,
+                Ok(Some(wait)) if error_mode.succeeded(wait) => Status::Done,
+                _ => Status::Failed,
+            };
+            unsquashfs.end(status, stopped);
+
+            wait
+        });
+
+        Control {
+            unsquashfs: self.clone(),
+            thread: Some(thread),
+        }
+    }
+
+    /// Builds the arguments of a command reading the image in `archive`,
+    /// optionally starting at byte `offset`.
+    fn image_args(
+        &self,
+        flag: &str,
+        archive: &Path,
+        offset: Option<u64>,
+    ) -> Result<Vec<OsString>, UnsquashfsError> {
+        let archive = resolve_archive(archive)?;
+        let mut args = vec![OsString::from(flag)];
+
+        if let Some(offset) = offset {
+            options::check_offset(&archive, offset)?;
+            self.require_flags(&["-offset"])?;
+            args.push("-offset".into());
+            args.push(offset.to_string().into());
+        }
+
+        args.push(archive.into());
+
+        Ok(args)
+    }
+
+    /// Runs `unsquashfs` with `args`, calling `on_line` for every line it
+    /// prints to stdout without buffering the whole output.
+    fn run_lines(
+        &self,
+        args: &[OsString],
+        mut on_line: impl FnMut(&str),
+    ) -> Result<(), UnsquashfsError> {
+        self.run(args, |stdout| {
+            let mut reader = BufReader::new(stdout);
+            let mut line = Vec::new();
+
+            loop {
+                line.clear();
+
+                if reader.read_until(b'\n', &mut line)? == 0 {
+                    return Ok(());
+                }
+
+                on_line(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']));
+            }
+        })
+    }
+
+    /// Runs `unsquashfs` with `args` without a pty, passing its stdout to `read`.
+    fn run<T>(
+        &self,
+        args: &[OsString],
+        read: impl FnOnce(ChildStdout) -> io::Result<T>,
+    ) -> Result<T, UnsquashfsError> {
+        let claim = self.begin()?;
+        let (binary, backend) = self.binary()?;
+
+        if backend != Backend::Unsquashfs {
+            return Err(UnsquashfsError::Unsupported(format!(
+                "{} with {backend:?} backend",
+                args[0].to_string_lossy()
+            )));
+        }
+
+        diag!(debug, "spawning {}", logging::command_line(&binary, args));
+
+        // Timestamps are printed in local time, use UTC so they can be parsed.
+        // Put it in its own process group like the pty does, so that signals
+        // sent to the group don't reach us.
+        let mut child = process::Command::new(&binary)
+            .args(args)
+            .process_group(0)
+            .env("TZ", "UTC")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(denied(Some(&binary)))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Failed to get stdout"))?;
+
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "Failed to get stderr"))?;
+
+        let process_control = self.control(claim, child, ErrorMode::Default, DEFAULT_CANCEL_GRACE);
+        // Read alongside stdout, so that a full stderr pipe can't block it.
+        let stderr = thread::spawn(move || read_stderr(stderr, None));
+
+        let res = read(stdout)?;
+
+        let buf = stderr.join().unwrap();
+        logging::stderr(&buf);
+
+        let wait = match process_control.join() {
+            Ok(wait) => wait,
+            Err(source) => {
+                return Err(UnsquashfsError::Failure {
+                    source,
+                    stderr: buf,
+                    exit_code: None,
+                    signal: None,
+                })
+            }
+        };
+
+        match wait {
+            Some(wait) if !wait.success() => {
+                diag!(error, "`unsquashfs` exited with {wait}");
+
+                Err(UnsquashfsError::Failure {
+                    source: Error::other(format!("`unsquashfs` exited with {wait}")),
+                    stderr: buf,
+                    exit_code: wait.code(),
+                    signal: wait.signal(),
+                })
+            }
+            Some(_) => Ok(res),
+            None => Err(UnsquashfsError::Cancelled),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{
+        env::temp_dir,
+        ffi::OsString,
+        fs, io,
+        ops::ControlFlow,
+        os::unix::{ffi::OsStringExt, fs::PermissionsExt, process::ExitStatusExt},
+        path::{Path, PathBuf},
+        process::ExitStatus,
+        sync::{atomic::Ordering, Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        extraction_failed, ErrorMode, ExtractOptions, ExtractSummary, IoPriority, Progress,
+        ProgressEvent, Status, Threads, Unsquashfs, UnsquashfsError, XattrPolicy,
+    };
+
+    /// Writes an executable shell script standing in for `unsquashfs`.
+    pub(crate) fn fake_binary(name: &str, script: &str) -> PathBuf {
+        let path = temp_dir().join("unsqfs-wrap-fake-bin").join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        fs::write(&path, format!("#!/bin/sh\n{script}")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn test_extract() {
+        let unsquashfs = Unsquashfs::default();
+        let unsquashfs_clone = unsquashfs.clone();
+
+        let t = thread::spawn(move || {
+            let output = temp_dir().join("unsqfs-wrap-test-extract");
+            fs::create_dir_all(&output).unwrap();
+            unsquashfs
                 .extract(
                     "testdata/test_extract.squashfs",
                     &output,
@@ -237,4 +1479,1261 @@ pub mod test {
 
         t.join().unwrap();
     }
+
+    #[test]
+    fn test_extract_non_utf8_dest() {
+        let output = temp_dir().join(OsString::from_vec(b"unsqfs-wrap-\xff\xfe".to_vec()));
+        fs::create_dir_all(&output).unwrap();
+
+        Unsquashfs::default()
+            .extract("testdata/test_extract.squashfs", &output, None, |_| {})
+            .unwrap();
+
+        assert!(fs::read_dir(&output).unwrap().next().is_some());
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn test_extract_dest_with_quote() {
+        let output = temp_dir().join("unsqfs-wrap-it's a dir");
+        fs::create_dir_all(&output).unwrap();
+
+        Unsquashfs::default()
+            .extract("testdata/test_extract.squashfs", &output, None, |_| {})
+            .unwrap();
+
+        assert!(fs::read_dir(&output).unwrap().next().is_some());
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn test_extract_with_binary() {
+        let binary = fake_binary(
+            "progress",
+            "printf '[=====     ] 1/2  50%%\\r[==========] 2/2 100%%\\n'",
+        );
+        let output = temp_dir().join("unsqfs-wrap-test-with-binary");
+        fs::create_dir_all(&output).unwrap();
+
+        let mut progress = Vec::new();
+        Unsquashfs::with_binary(binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().dest(&output),
+                |c| progress.push(c),
+            )
+            .unwrap();
+
+        assert_eq!(progress, [50, 100]);
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn test_unprivileged_extract() {
+        let binary = fake_binary(
+            "user-xattrs",
+            "[ \"$1\" = -help ] && { echo ' -no-xattrs'; exit 0; }
+echo \"$@\" > \"$0.args\"
+echo 'write_xattr: could not write xattr trusted.overlay for file a' >&2
+echo 'FATAL ERROR: failed to write file b' >&2
+exit 1",
+        );
+
+        let err = Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().xattrs(XattrPolicy::UserOnly),
+                |_| {},
+            )
+            .unwrap_err();
+
+        let UnsquashfsError::Failure { stderr: output, .. } = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(!output.contains("xattr"));
+        assert!(output.contains("FATAL ERROR"));
+
+        // The fake binary doesn't know `-user-xattrs`, so xattrs are skipped.
+        let args = fs::read_to_string(binary.with_extension("args")).unwrap();
+        assert!(args.contains("-no-xattrs") && !args.contains("-user-xattrs"));
+    }
+
+    #[test]
+    fn test_no_space() {
+        let binary = fake_binary(
+            "no-space",
+            "printf '[=====     ] 1/2  50%%\\r'
+echo 'write_file: failed to write file usr/lib/libc.so.6, because No space left on device' >&2
+exit 1",
+        );
+        let output = temp_dir().join("unsqfs-wrap-test-no-space/dest");
+
+        let err = Unsquashfs::with_binary(binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().dest(&output),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            UnsquashfsError::DiskFull {
+                destination,
+                bytes_written_estimate: Some(estimate),
+                stderr: Some(stderr),
+            } if destination == output && estimate > 0 && stderr.contains("No space left")
+        ));
+    }
+
+    #[test]
+    fn test_destination_not_empty() {
+        let binary = fake_binary(
+            "no-overwrite",
+            "echo 'write_file: failed to create file dest/etc/os-release, because File exists' >&2
+exit 2",
+        );
+
+        let err = Unsquashfs::with_binary(binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().force(false),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(
+            matches!(err, UnsquashfsError::DestinationNotEmpty { path, stderr: Some(stderr) }
+                if path == Path::new("dest/etc/os-release") && stderr.contains("File exists"))
+        );
+    }
+
+    #[test]
+    fn test_classify_stderr() {
+        let failed = |stderr: &str| {
+            extraction_failed(ExitStatus::from_raw(1 << 8), stderr.to_string(), None, None)
+        };
+
+        assert!(matches!(
+            failed("Can't find a SQUASHFS superblock on image.squashfs\n"),
+            UnsquashfsError::Corrupted { path: None, .. }
+        ));
+        assert!(matches!(
+            failed(
+                "read_block: failed to read block @0x1a2b\nFATAL ERROR: Filesystem corrupted?\n"
+            ),
+            UnsquashfsError::Corrupted { path: None, .. }
+        ));
+        assert!(matches!(
+            failed("Filesystem uses lzo compression, this is unsupported by this version\nDecompressors available:\n\tgzip\n"),
+            UnsquashfsError::UnsupportedCompression { compression, available, .. }
+                if compression == "lzo" && available == ["gzip"]
+        ));
+        assert!(matches!(
+            failed("write_file: failed to create file dest/etc/shadow, because Input/output error\n"),
+            UnsquashfsError::WriteFailed { path, stderr }
+                if path == Path::new("dest/etc/shadow") && stderr.contains("Input/output error")
+        ));
+        assert!(matches!(
+            failed("write_file: failed to create file dest/etc/shadow, because Permission denied\n"),
+            UnsquashfsError::PermissionDenied { path: Some(path), stderr: Some(stderr) }
+                if path == Path::new("dest/etc/shadow") && stderr.contains("Permission denied")
+        ));
+        assert!(matches!(
+            failed("create_inode: could not create character device dest/dev/null, because you're not superuser!\n"),
+            UnsquashfsError::PermissionDenied { path: Some(path), .. } if path == Path::new("dest/dev/null")
+        ));
+        assert!(matches!(
+            failed("create_inode: failed to create hardlink, because Operation not permitted\n"),
+            UnsquashfsError::PermissionDenied { path: None, .. }
+        ));
+        assert!(matches!(
+            failed("FATAL ERROR: Out of memory\n"),
+            UnsquashfsError::Failure {
+                exit_code: Some(1),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_extract_stats() {
+        let binary = fake_binary(
+            "summary",
+            "printf '[==========] 2/2 100%%\\n\\ncreated 1 file\\ncreated 1 directory\\ncreated 0 symlinks\\n'",
+        );
+        let output = temp_dir().join("unsqfs-wrap-test-stats");
+        fs::create_dir_all(&output).unwrap();
+        fs::write(output.join("a"), "hello").unwrap();
+
+        let summary = Unsquashfs::with_binary(binary)
+            .extract_stats(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new()
+                    .dest(&output)
+                    .threads(Threads::HalfCores),
+                |_| {},
+            )
+            .unwrap();
+
+        assert_eq!(
+            summary,
+            ExtractSummary {
+                files: 1,
+                dirs: 1,
+                symlinks: 0,
+                devices: 0,
+                bytes: 5,
+                processors: Some(Threads::HalfCores.count()),
+                errors: Vec::new(),
+            }
+        );
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn test_timeout() {
+        let binary = fake_binary(
+            "stuck",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=====     ] 1/2  50%%\\r'; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let mut progress = Vec::new();
+        let err = unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().timeout(Duration::from_millis(200)),
+                |c| progress.push(c),
+            )
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::Timeout(_)));
+        assert_eq!(progress, [50]);
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+    }
+
+    #[test]
+    fn test_time_limit() {
+        // Keeps making progress, so only the time limit stops it.
+        let binary = fake_binary(
+            "time-limit",
+            "[ \"$1\" = -help ] && exit 0
+for i in 1 2 3 4 5 6 7 8 9; do printf \"[=     ] $i/100  $i%%\\r\"; sleep 0.05; done; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let limit = Duration::from_millis(200);
+        let err = unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new()
+                    .timeout(Duration::from_secs(5))
+                    .time_limit(limit),
+                |_| {},
+            )
+            .unwrap_err();
+
+        let UnsquashfsError::TimedOut { elapsed, percent } = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(elapsed >= limit && elapsed < limit + Duration::from_millis(100));
+        assert!((1..9).contains(&percent));
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+    }
+
+    #[test]
+    fn test_extract_with_files() {
+        let binary = fake_binary(
+            "info",
+            "for i in $(seq 30); do printf 'squashfs-root/dir/file %s\\n[===   ] %s/30  50%%\\r' $i $i; done
+printf '[======] 30/30 100%%\\n'",
+        );
+
+        let mut files = Vec::new();
+        Unsquashfs::with_binary(binary)
+            .extract_with_files(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+                |file| files.push(file.to_path_buf()),
+            )
+            .unwrap();
+
+        let expected: Vec<PathBuf> = (1..=30)
+            .map(|i| PathBuf::from(format!("/dir/file {i}")))
+            .collect();
+        assert_eq!(files, expected);
+    }
+
+    #[test]
+    fn test_on_file() {
+        let binary = fake_binary(
+            "on-file",
+            "[ \"$1\" = -help ] && echo '-percentage -info' && exit 0
+echo \"$@\" > \"$0.args\"
+printf 'squashfs-root/etc/fstab\\n50\\nsquashfs-root/etc/os-release\\n100\\n'",
+        );
+
+        let files = Arc::new(Mutex::new(Vec::new()));
+        let options = ExtractOptions::new().quiet(true).on_file({
+            let files = files.clone();
+            move |file| files.lock().unwrap().push(file.to_path_buf())
+        });
+
+        let mut progress = Vec::new();
+        Unsquashfs::with_binary(&binary)
+            .extract_with("testdata/test_extract.squashfs", &options, |p| {
+                progress.push(p)
+            })
+            .unwrap();
+
+        assert_eq!(progress, [50, 100]);
+        assert_eq!(
+            *files.lock().unwrap(),
+            [Path::new("/etc/fstab"), Path::new("/etc/os-release")]
+        );
+
+        let args = fs::read_to_string(binary.with_extension("args")).unwrap();
+        assert!(args.contains("-info") && !args.contains("-q "));
+    }
+
+    #[test]
+    fn test_cancel_blocking() {
+        let binary = fake_binary(
+            "slow",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let unsquashfs_clone = unsquashfs.clone();
+
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        unsquashfs.cancel_blocking().unwrap();
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+        t.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_extract_with_events() {
+        let binary = fake_binary(
+            "events",
+            "printf '[=====     ] 1/2  50%%\\r[==========] 2/2 100%%\\n'",
+        );
+
+        let mut events = Vec::new();
+        Unsquashfs::with_binary(binary)
+            .extract_with_events(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |event| events.push(event),
+            )
+            .unwrap();
+
+        assert_eq!(
+            events,
+            [
+                ProgressEvent::Started,
+                ProgressEvent::Progress { percent: 50 },
+                ProgressEvent::Progress { percent: 100 },
+                ProgressEvent::Finished,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cancel_terminates_first() {
+        let binary = fake_binary(
+            "cancel-term",
+            "[ \"$1\" = -help ] && exit 0
+rm -f \"$0.term\"
+trap 'echo flushed > \"$0.term\"; exit 0' TERM
+printf '[=     ] 1/9  10%%\\r'; while :; do sleep 0.01; done",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        unsquashfs.cancel_blocking().unwrap();
+        t.join().unwrap().unwrap();
+
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+        assert_eq!(
+            fs::read_to_string(binary.with_extension("term")).unwrap(),
+            "flushed\n"
+        );
+    }
+
+    #[test]
+    fn test_cancel_kills_after_grace() {
+        let binary = fake_binary(
+            "cancel-ignore-term",
+            "[ \"$1\" = -help ] && exit 0
+trap '' TERM
+printf '[=     ] 1/9  10%%\\r'; while :; do sleep 0.01; done",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let grace = Duration::from_millis(200);
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().cancel_grace(grace),
+                |_| {},
+            )
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let start = Instant::now();
+        assert!(matches!(
+            unsquashfs.cancel_and_wait(Some(Duration::from_millis(50))),
+            Err(UnsquashfsError::CancelTimeout(_))
+        ));
+        assert!(unsquashfs.cancel_and_wait(None).unwrap());
+        t.join().unwrap().unwrap();
+
+        assert!(start.elapsed() >= grace);
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+        assert!(unsquashfs.pid.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_panicking_callback_stops_child() {
+        let binary = fake_binary(
+            "panic-callback",
+            "[ \"$1\" = -help ] && exit 0
+echo $$ > \"$0.pid\"
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| panic!("the callback gave up"),
+            )
+        });
+
+        assert!(t.join().is_err());
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+
+        let pid = fs::read_to_string(binary.with_extension("pid")).unwrap();
+        assert!(!Path::new("/proc").join(pid.trim()).exists());
+    }
+
+    #[test]
+    fn test_failed_run_stops_watchdog() {
+        let binary = fake_binary(
+            "watchdog-stopped",
+            "[ \"$1\" = -help ] && exit 0
+for i in 1 2 3 4 5 6; do printf \"[=     ] $i/6  ${i}0%%\\r\"; sleep 0.1; done
+printf '[======] 6/6 100%%\\n'",
+        );
+
+        let limit = Duration::from_millis(150);
+
+        for options in [
+            ExtractOptions::new().timeout(limit),
+            ExtractOptions::new().time_limit(limit),
+        ] {
+            let unsquashfs = Unsquashfs::with_binary(&binary);
+            let unsquashfs_clone = unsquashfs.clone();
+            let t = thread::spawn(move || {
+                unsquashfs_clone.extract_with("testdata/test_extract.squashfs", &options, |_| {
+                    panic!("the callback gave up")
+                })
+            });
+            assert!(t.join().is_err());
+
+            // Outlives the limit of the failed run, without one of its own.
+            unsquashfs
+                .extract_with(
+                    "testdata/test_extract.squashfs",
+                    &ExtractOptions::new(),
+                    |_| {},
+                )
+                .unwrap();
+            assert_eq!(unsquashfs.status(), Status::Done);
+        }
+    }
+
+    #[test]
+    fn test_cleanup_on_cancel() {
+        let binary = fake_binary(
+            "partial",
+            "[ \"$1\" = -help ] && exit 0
+mkdir -p \"$4/usr\"; touch \"$4/usr/a\" \"$4/new\"
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+        let output = temp_dir().join("unsqfs-wrap-test-cleanup-cancel");
+        fs::create_dir_all(&output).unwrap();
+        fs::write(output.join("user-data"), "keep").unwrap();
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let options = ExtractOptions::new().dest(&output).cleanup_on_cancel(true);
+
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with("testdata/test_extract.squashfs", &options, |_| {})
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        unsquashfs.cancel().unwrap();
+        t.join().unwrap().unwrap();
+
+        let mut left: Vec<_> = fs::read_dir(&output)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["user-data"]);
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn test_extract_percentage() {
+        let binary = fake_binary(
+            "percentage",
+            "[ \"$1\" = -help ] && { echo ' -percentage'; exit 0; }
+echo \"$@\" > \"$0.args\"
+env > \"$0.env\"
+printf '50\\n100\\n'",
+        );
+
+        let mut progress = Vec::new();
+        Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |c| progress.push(c),
+            )
+            .unwrap();
+
+        assert_eq!(progress, [50, 100]);
+
+        let args = fs::read_to_string(binary.with_extension("args")).unwrap();
+        assert!(args.contains("-percentage"));
+
+        let env = fs::read_to_string(binary.with_extension("env")).unwrap();
+        assert!(!env.contains("TERM=xterm-256color"));
+    }
+
+    #[test]
+    fn test_extract_percentage_from_zero() {
+        let binary = fake_binary(
+            "percentage-from-zero",
+            "[ \"$1\" = -help ] && { echo ' -percentage'; exit 0; }
+printf '0\\n0\\n40\\n100\\n'",
+        );
+
+        let mut progress = Vec::new();
+        Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |c| progress.push(c),
+            )
+            .unwrap();
+
+        assert_eq!(progress, [0, 40, 100]);
+    }
+
+    #[test]
+    fn test_extract_env() {
+        let binary = fake_binary(
+            "env",
+            "[ \"$1\" = -help ] && exit 0
+env > \"$0.env\"
+printf '[==========] 1/1 100%%\\n'",
+        );
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let read_env = || fs::read_to_string(binary.with_extension("env")).unwrap();
+
+        unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap();
+        let env = read_env();
+        assert!(!env.lines().any(|var| var.starts_with("COLUMNS=")));
+        assert!(!env.lines().any(|var| var.starts_with("LINES=")));
+
+        unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new()
+                    .env("COLUMNS", "120")
+                    .env("UNSQFS_WRAP_TEST", "a b"),
+                |_| {},
+            )
+            .unwrap();
+        let env = read_env();
+        assert!(env.lines().any(|var| var == "COLUMNS=120"));
+        assert!(env.lines().any(|var| var == "UNSQFS_WRAP_TEST=a b"));
+        assert!(!env.lines().any(|var| var.starts_with("LINES=")));
+    }
+
+    #[test]
+    fn test_extract_progress_bar_fallback() {
+        let binary = fake_binary(
+            "no-percentage",
+            "[ \"$1\" = -help ] && { echo ' -info'; exit 0; }
+echo \"$@\" > \"$0.args\"
+printf '[=====     ] 1/2  50%%\\r[==========] 2/2 100%%\\n'",
+        );
+
+        let mut progress = Vec::new();
+        Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |c| progress.push(c),
+            )
+            .unwrap();
+
+        assert_eq!(progress, [50, 100]);
+
+        let args = fs::read_to_string(binary.with_extension("args")).unwrap();
+        assert!(!args.contains("-percentage"));
+    }
+
+    #[test]
+    fn test_extract_controlled() {
+        let binary = fake_binary(
+            "controlled",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r[==     ] 2/9  20%%\\r'; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let mut progress = Vec::new();
+        let err = unsquashfs
+            .extract_controlled(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |p| {
+                    progress.push(p.percent);
+                    ControlFlow::Break(())
+                },
+            )
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::Cancelled));
+        assert_eq!(progress, [10]);
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+    }
+
+    #[test]
+    fn test_extract_try() {
+        let binary = fake_binary(
+            "try",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let err = unsquashfs
+            .extract_try(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {
+                    Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "status pipe closed",
+                    ))
+                },
+            )
+            .unwrap_err();
+
+        assert!(
+            matches!(err, UnsquashfsError::Callback(e) if e.to_string() == "status pipe closed")
+        );
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+    }
+
+    #[test]
+    fn test_on_stderr() {
+        let binary = fake_binary(
+            "stderr",
+            "echo 'ignoring extended attributes' >&2
+printf '[==========] 2/2 100%%\\n'
+echo 'FATAL ERROR: failed' >&2
+exit 1",
+        );
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let options = ExtractOptions::new().on_stderr({
+            let lines = lines.clone();
+            move |line| lines.lock().unwrap().push(line.to_string())
+        });
+
+        let err = Unsquashfs::with_binary(binary)
+            .extract_with("testdata/test_extract.squashfs", &options, |_| {})
+            .unwrap_err();
+
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["ignoring extended attributes", "FATAL ERROR: failed"]
+        );
+        assert!(matches!(
+            err,
+            UnsquashfsError::Failure { stderr: output, .. } if output == "ignoring extended attributes\nFATAL ERROR: failed\n"
+        ));
+    }
+
+    #[test]
+    fn test_ignore_errors() {
+        let binary = fake_binary(
+            "ignore-errors",
+            "[ \"$1\" = -help ] && echo '-ignore-errors' && exit 0
+echo 'write_file: failed to create file etc/shadow, because Permission denied' >&2
+printf '[==========] 2/2 100%%\n'
+exit 2",
+        );
+        let unsquashfs = Unsquashfs::with_binary(binary);
+
+        let summary = unsquashfs
+            .extract_stats(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().error_mode(ErrorMode::Ignore),
+                |_| {},
+            )
+            .unwrap();
+        assert_eq!(
+            summary.errors,
+            ["write_file: failed to create file etc/shadow, because Permission denied"]
+        );
+        assert_eq!(unsquashfs.status(), Status::Done);
+
+        let err = unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            UnsquashfsError::PermissionDenied { path: Some(path), .. } if path == Path::new("etc/shadow")
+        ));
+        assert_eq!(unsquashfs.status(), Status::Failed);
+    }
+
+    #[test]
+    fn test_final_progress() {
+        let binary = fake_binary("final-progress", "printf '[========= ] 99/100  99%%\\r'");
+
+        let mut progress = Vec::new();
+        Unsquashfs::with_binary(binary)
+            .extract_with_progress(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |p| progress.push(p),
+            )
+            .unwrap();
+        assert_eq!(
+            progress.last(),
+            Some(&Progress {
+                percent: 100,
+                done: Some(100),
+                total: Some(100),
+            })
+        );
+
+        // Only a successful extraction is completed.
+        let binary = fake_binary(
+            "final-progress-fail",
+            "printf '[========= ] 99/100  99%%\\r'; exit 1",
+        );
+
+        let mut percents = Vec::new();
+        Unsquashfs::with_binary(binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |p| percents.push(p),
+            )
+            .unwrap_err();
+        assert_eq!(percents, [99]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_nice() {
+        // The 19th field of `stat` is the niceness.
+        let binary = fake_binary("nice", "cut -d' ' -f19 /proc/$$/stat > \"$0.nice\"");
+
+        Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().nice(10).io_priority(IoPriority::Idle),
+                |_| {},
+            )
+            .unwrap();
+
+        let nice = fs::read_to_string(binary.with_extension("nice")).unwrap();
+        assert_eq!(nice.trim_end(), "10");
+    }
+
+    #[test]
+    fn test_cgroup() {
+        let binary = fake_binary("cgroup", "");
+        // A real cgroup turns the 0 written to it into the PID.
+        let cgroup = temp_dir().join("unsqfs-wrap-test-cgroup");
+        fs::create_dir_all(&cgroup).unwrap();
+        fs::write(cgroup.join("cgroup.procs"), "").unwrap();
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().cgroup(&cgroup),
+                |_| {},
+            )
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(cgroup.join("cgroup.procs")).unwrap(),
+            "0"
+        );
+
+        let err = unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().cgroup(cgroup.join("missing")),
+                |_| {},
+            )
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+        assert_eq!(unsquashfs.status(), Status::Done);
+    }
+
+    #[test]
+    fn test_extract_no_progress() {
+        let binary = fake_binary(
+            "no-progress",
+            r#"[ "$1" = -help ] && echo '-no-progress' && exit 0
+echo "$@" > "$0.args"
+case "$(cat "$0.mode")" in
+    fail) echo 'FATAL ERROR: Out of memory' >&2; exit 1 ;;
+    slow) exec sleep 10 ;;
+esac"#,
+        );
+        let mode = binary.with_extension("mode");
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+
+        fs::write(&mode, "").unwrap();
+        unsquashfs
+            .extract_no_progress("testdata/test_extract.squashfs", &ExtractOptions::new())
+            .unwrap();
+        let args = fs::read_to_string(binary.with_extension("args")).unwrap();
+        assert!(args.contains("-no-progress") && !args.contains("-percentage"));
+        assert_eq!(unsquashfs.status(), Status::Done);
+
+        fs::write(&mode, "fail").unwrap();
+        let err = unsquashfs
+            .extract_no_progress("testdata/test_extract.squashfs", &ExtractOptions::new())
+            .unwrap_err();
+        assert!(
+            matches!(err, UnsquashfsError::Failure { stderr, .. } if stderr.contains("Out of memory"))
+        );
+
+        fs::write(&mode, "slow").unwrap();
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone
+                .extract_no_progress("testdata/test_extract.squashfs", &ExtractOptions::new())
+        });
+        while !unsquashfs.is_working() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        unsquashfs.cancel_blocking().unwrap();
+        t.join().unwrap().unwrap();
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+
+        let err = unsquashfs
+            .extract_no_progress(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new().timeout(Duration::from_secs(1)),
+            )
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+    }
+
+    #[test]
+    fn test_binary_not_executable() {
+        // Running it fails rather than looking it up, as the interpreter can't
+        // be executed.
+        let interpreter = fake_binary("not-executable-interpreter", "");
+        fs::set_permissions(&interpreter, fs::Permissions::from_mode(0o644)).unwrap();
+        let binary = interpreter.with_file_name("not-executable");
+        fs::write(&binary, format!("#!{}\n", interpreter.display())).unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let err = unsquashfs
+            .extract_with_progress(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            UnsquashfsError::PermissionDenied { path: Some(path), stderr: None } if path == binary
+        ));
+        assert_eq!(unsquashfs.status(), Status::Pending);
+    }
+
+    #[test]
+    fn test_is_working() {
+        let binary = fake_binary(
+            "is-working",
+            "printf '[=====     ] 1/2  50%%\\r'; sleep 0.1",
+        );
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        assert!(!unsquashfs.is_working());
+
+        let mut seen = Vec::new();
+        unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| seen.push((unsquashfs.is_working(), unsquashfs.status())),
+            )
+            .unwrap();
+
+        assert_eq!(seen[0], (true, Status::Working { percent: 50 }));
+        assert!(!unsquashfs.is_working());
+        assert_eq!(unsquashfs.status(), Status::Done);
+    }
+
+    #[test]
+    fn test_busy() {
+        let binary = fake_binary(
+            "busy",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r'; sleep 0.2",
+        );
+        let unsquashfs = Unsquashfs::with_binary(binary);
+
+        let results: Vec<_> = (0..2)
+            .map(|_| {
+                let unsquashfs = unsquashfs.clone();
+                thread::spawn(move || {
+                    unsquashfs.extract_with(
+                        "testdata/test_extract.squashfs",
+                        &ExtractOptions::new(),
+                        |_| {},
+                    )
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect();
+
+        assert_eq!(results.iter().filter(|res| res.is_ok()).count(), 1);
+        assert!(results
+            .iter()
+            .any(|res| matches!(res, Err(UnsquashfsError::Busy))));
+        assert_eq!(unsquashfs.status(), Status::Done);
+
+        // Failing before anything is spawned gives the instance back.
+        unsquashfs
+            .extract_with("testdata/missing.squashfs", &ExtractOptions::new(), |_| {})
+            .unwrap_err();
+        assert_eq!(unsquashfs.status(), Status::Done);
+    }
+
+    #[test]
+    fn test_killed_exit_code() {
+        let binary = fake_binary("killed", "kill -9 $$");
+
+        let err = Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            UnsquashfsError::Failure {
+                exit_code: None,
+                signal: Some(libc::SIGKILL),
+                ..
+            }
+        ));
+
+        let binary = fake_binary("exit-1", "exit 1");
+        let err = Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            UnsquashfsError::Failure {
+                exit_code: Some(1),
+                signal: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_reuse_after_cancel() {
+        let binary = fake_binary(
+            "reuse",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r'
+[ -e \"$0.slow\" ] && exec sleep 10
+printf '[==========] 9/9 100%%\\n'",
+        );
+        fs::write(binary.with_extension("slow"), "").unwrap();
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        unsquashfs.cancel_blocking().unwrap();
+        t.join().unwrap().unwrap();
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+
+        // A flag left over from a cancel that came too late doesn't stop the
+        // next run.
+        unsquashfs.cancel.store(true, Ordering::SeqCst);
+        fs::remove_file(binary.with_extension("slow")).unwrap();
+
+        let mut percents = Vec::new();
+        unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |p| percents.push(p),
+            )
+            .unwrap();
+        assert_eq!(percents, [10, 100]);
+        assert_eq!(unsquashfs.status(), Status::Done);
+    }
+
+    #[test]
+    fn test_status() {
+        let unsquashfs = Unsquashfs::with_binary(fake_binary("status-ok", "exit 0"));
+        assert_eq!(unsquashfs.status(), Status::Pending);
+
+        unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap();
+        assert_eq!(unsquashfs.status(), Status::Done);
+        assert!(!unsquashfs.status().is_running());
+
+        let unsquashfs = Unsquashfs::with_binary(fake_binary("status-fail", "exit 1"));
+        unsquashfs
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap_err();
+        assert_eq!(unsquashfs.status(), Status::Failed);
+    }
+
+    #[test]
+    fn test_extract_excluding() {
+        let unsquashfs = Unsquashfs::default();
+        let archive = "testdata/test_extract.squashfs";
+
+        let entries = unsquashfs.list(archive).unwrap();
+        let top_level: Vec<_> = entries.iter().filter(|p| p.rfind('/') == Some(0)).collect();
+        let (excluded, kept) = top_level.split_first().unwrap();
+
+        let output = temp_dir().join("unsqfs-wrap-test-exclude");
+        fs::create_dir_all(&output).unwrap();
+
+        let mut progress = Vec::new();
+        unsquashfs
+            .extract_with(
+                archive,
+                &ExtractOptions::new()
+                    .dest(&output)
+                    .exclude([&excluded[1..]]),
+                |c| progress.push(c),
+            )
+            .unwrap();
+
+        let extracted = |p: &str| output.join(&p[1..]).symlink_metadata().is_ok();
+        assert!(!extracted(excluded));
+        assert!(kept.iter().all(|p| extracted(p)));
+        assert!(!progress.is_empty());
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn test_extract_at_offset() {
+        let archive = temp_dir().join("unsqfs-wrap-test-offset.img");
+        let mut image = vec![0xaa; 4096];
+        image.extend(fs::read("testdata/test_extract.squashfs").unwrap());
+        fs::write(&archive, image).unwrap();
+
+        let output = temp_dir().join("unsqfs-wrap-test-offset");
+        fs::create_dir_all(&output).unwrap();
+
+        let unsquashfs = Unsquashfs::default();
+        unsquashfs
+            .extract_with(
+                &archive,
+                &ExtractOptions::new().dest(&output).offset(4096),
+                |_| {},
+            )
+            .unwrap();
+
+        assert!(fs::read_dir(&output).unwrap().next().is_some());
+        assert_eq!(
+            unsquashfs.list_at(&archive, 4096).unwrap(),
+            unsquashfs.list("testdata/test_extract.squashfs").unwrap()
+        );
+        fs::remove_dir_all(output).unwrap();
+        fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn test_offset_unsupported() {
+        let binary = fake_binary(
+            "no-offset",
+            "echo 'unsquashfs [options] filesystem' >&2; exit 1",
+        );
+
+        let err = Unsquashfs::with_binary(binary)
+            .stat_at("testdata/test_extract.squashfs", 0)
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::UnsupportedFlag(flag) if flag == "-offset"));
+    }
+
+    #[test]
+    fn test_extract_paths_empty() {
+        let err = Unsquashfs::new()
+            .extract_paths(
+                "testdata/test_extract.squashfs",
+                temp_dir(),
+                &[] as &[&str],
+                None,
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+    }
+
+    #[test]
+    fn test_non_executable_binary() {
+        let binary = fake_binary("not-executable", "exit 0");
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let err = Unsquashfs::with_binary(&binary)
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(
+            matches!(err, UnsquashfsError::BinaryDoesNotExist { binary: p, .. } if p == binary)
+        );
+    }
+
+    #[test]
+    fn test_missing_binary() {
+        let err = Unsquashfs::with_binary("/nonexistent/unsquashfs")
+            .extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .unwrap_err();
+
+        assert!(
+            matches!(err, UnsquashfsError::BinaryDoesNotExist { binary: p, .. } if p == Path::new("/nonexistent/unsquashfs"))
+        );
+    }
+
+    #[test]
+    fn test_missing_archive() {
+        let binary = fake_binary(
+            "missing-archive",
+            "[ \"$1\" = -help ] && exit 0
+echo \"$@\" > \"$0.args\"",
+        );
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        fs::remove_file(binary.with_extension("args")).ok();
+
+        let err = unsquashfs
+            .extract_with("testdata/missing.squashfs", &ExtractOptions::new(), |_| {})
+            .unwrap_err();
+        assert!(
+            matches!(err, UnsquashfsError::ArchiveNotFound(p) if p == Path::new("testdata/missing.squashfs"))
+        );
+
+        let err = unsquashfs.stat("testdata/missing.squashfs").unwrap_err();
+        assert!(matches!(err, UnsquashfsError::ArchiveNotFound(_)));
+
+        // Nothing was spawned.
+        assert!(!binary.with_extension("args").exists());
+    }
 }
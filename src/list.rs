@@ -0,0 +1,280 @@
+use std::path::{Path, PathBuf};
+
+use crate::{Unsquashfs, UnsquashfsError};
+
+/// A single entry of a long listing (`unsquashfs -ll`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListEntry {
+    /// Permission string as printed by `unsquashfs`, e.g. `drwxr-xr-x`.
+    pub mode: String,
+    pub owner: String,
+    pub group: String,
+    /// Size in bytes, `0` for devices.
+    pub size: u64,
+    /// Path relative to the image root, with a leading `/`.
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink { target: PathBuf },
+    CharDevice { major: u32, minor: u32 },
+    BlockDevice { major: u32, minor: u32 },
+    Fifo,
+    Socket,
+}
+
+impl ListEntry {
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Directory
+    }
+}
+
+/// Name of the root directory `unsquashfs` prefixes listed paths with.
+const ROOT: &str = "squashfs-root";
+
+impl Unsquashfs {
+    /// Lists the paths inside an image without extracting it (`unsquashfs -l`).
+    ///
+    /// Paths are returned relative to the image root with a leading `/`, and
+    /// the root directory itself is omitted. Listing can be stopped with
+    /// [`Unsquashfs::cancel`], which makes it return
+    /// [`UnsquashfsError::Cancelled`].
+    pub fn list(&self, archive: impl AsRef<Path>) -> Result<Vec<String>, UnsquashfsError> {
+        self.list_inner(archive.as_ref(), None)
+    }
+
+    /// Lists the paths of an image starting at byte `offset` inside `archive`.
+    pub fn list_at(
+        &self,
+        archive: impl AsRef<Path>,
+        offset: u64,
+    ) -> Result<Vec<String>, UnsquashfsError> {
+        self.list_inner(archive.as_ref(), Some(offset))
+    }
+
+    /// Lists the entries inside an image with their permissions, ownership
+    /// and sizes (`unsquashfs -ll`).
+    ///
+    /// Like [`Unsquashfs::list`], this can be stopped with [`Unsquashfs::cancel`].
+    pub fn list_long(&self, archive: impl AsRef<Path>) -> Result<Vec<ListEntry>, UnsquashfsError> {
+        self.list_long_inner(archive.as_ref(), None)
+    }
+
+    /// Lists the entries of an image starting at byte `offset` inside `archive`.
+    pub fn list_long_at(
+        &self,
+        archive: impl AsRef<Path>,
+        offset: u64,
+    ) -> Result<Vec<ListEntry>, UnsquashfsError> {
+        self.list_long_inner(archive.as_ref(), Some(offset))
+    }
+
+    fn list_inner(
+        &self,
+        archive: &Path,
+        offset: Option<u64>,
+    ) -> Result<Vec<String>, UnsquashfsError> {
+        let args = self.image_args("-l", archive, offset)?;
+        let mut paths = Vec::new();
+
+        self.run_lines(&args, |line| {
+            if let Some(path) = strip_root(line) {
+                paths.push(path.to_string());
+            }
+        })?;
+
+        Ok(paths)
+    }
+
+    fn list_long_inner(
+        &self,
+        archive: &Path,
+        offset: Option<u64>,
+    ) -> Result<Vec<ListEntry>, UnsquashfsError> {
+        let args = self.image_args("-ll", archive, offset)?;
+        let mut entries = Vec::new();
+
+        self.run_lines(&args, |line| {
+            if let Some(entry) = parse_long_line(line) {
+                entries.push(entry);
+            }
+        })?;
+
+        Ok(entries)
+    }
+}
+
+/// Splits off the first whitespace-separated token of `s`.
+fn next_token(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    let end = s.find(char::is_whitespace)?;
+
+    Some((&s[..end], &s[end..]))
+}
+
+/// Parses a line such as
+/// `-rw-r--r-- root/root  6 2024-09-16 10:00 squashfs-root/a`.
+pub(crate) fn parse_long_line(line: &str) -> Option<ListEntry> {
+    let (mode, rest) = next_token(line)?;
+    let (owner_group, rest) = next_token(rest)?;
+    let (owner, group) = owner_group.split_once('/')?;
+
+    let (size, device, rest) = match mode.chars().next()? {
+        c @ ('c' | 'b') => {
+            let (major, rest) = next_token(rest)?;
+            let (minor, rest) = next_token(rest)?;
+            let major = major.trim_end_matches(',').parse().ok()?;
+            let minor = minor.parse().ok()?;
+            (0, Some((c, major, minor)), rest)
+        }
+        _ => {
+            let (size, rest) = next_token(rest)?;
+            (size.parse().ok()?, None, rest)
+        }
+    };
+
+    // Skip the modification date and time.
+    let (_, rest) = next_token(rest)?;
+    let (_, rest) = next_token(rest)?;
+    let rest = rest.trim_start();
+
+    let (path, target) = match rest.split_once(" -> ") {
+        Some((path, target)) if mode.starts_with('l') => (path, Some(target)),
+        _ => (rest, None),
+    };
+
+    let path = strip_root(path)?;
+
+    let kind = match (mode.chars().next()?, device, target) {
+        ('d', _, _) => EntryKind::Directory,
+        ('l', _, Some(target)) => EntryKind::Symlink {
+            target: PathBuf::from(target),
+        },
+        (_, Some(('c', major, minor)), _) => EntryKind::CharDevice { major, minor },
+        (_, Some((_, major, minor)), _) => EntryKind::BlockDevice { major, minor },
+        ('p', _, _) => EntryKind::Fifo,
+        ('s', _, _) => EntryKind::Socket,
+        _ => EntryKind::File,
+    };
+
+    Some(ListEntry {
+        mode: mode.to_string(),
+        owner: owner.to_string(),
+        group: group.to_string(),
+        size,
+        path: PathBuf::from(path),
+        kind,
+    })
+}
+
+/// Strips the `squashfs-root` prefix from a listed path, returning `None`
+/// for the root itself and for lines that are not paths.
+fn strip_root(line: &str) -> Option<&str> {
+    line.strip_prefix(ROOT).filter(|path| path.starts_with('/'))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, thread, time::Duration};
+
+    use super::parse_long_line;
+    use crate::{test::fake_binary, EntryKind, Status, Unsquashfs, UnsquashfsError};
+
+    #[test]
+    fn test_list() {
+        let binary = fake_binary(
+            "list",
+            "printf 'squashfs-root\\nsquashfs-root/etc\\nsquashfs-root/etc/os release\\n'",
+        );
+
+        let paths = Unsquashfs::with_binary(binary)
+            .list("testdata/test_extract.squashfs")
+            .unwrap();
+
+        assert_eq!(paths, ["/etc", "/etc/os release"]);
+    }
+
+    #[test]
+    fn test_list_failure() {
+        let binary = fake_binary(
+            "list-corrupt",
+            "echo 'Can'\\''t find a SQUASHFS superblock' >&2; exit 1",
+        );
+
+        let err = Unsquashfs::with_binary(binary)
+            .list("testdata/test_extract.squashfs")
+            .unwrap_err();
+
+        assert!(
+            matches!(err, UnsquashfsError::Failure { stderr, .. } if stderr.contains("superblock"))
+        );
+    }
+
+    #[test]
+    fn test_parse_long_line() {
+        let dir = parse_long_line(
+            "drwxr-xr-x root/root                38 2024-09-16 10:00 squashfs-root/etc",
+        )
+        .unwrap();
+        assert!(dir.is_dir());
+        assert_eq!(dir.size, 38);
+        assert_eq!(dir.path, PathBuf::from("/etc"));
+
+        let file = parse_long_line(
+            "-rw-r--r-- saki/users         1234567 2024-09-16 10:00 squashfs-root/a b",
+        )
+        .unwrap();
+        assert_eq!(file.kind, EntryKind::File);
+        assert_eq!(
+            (file.owner.as_str(), file.group.as_str()),
+            ("saki", "users")
+        );
+        assert_eq!(file.size, 1234567);
+        assert_eq!(file.path, PathBuf::from("/a b"));
+
+        let link = parse_long_line(
+            "lrwxrwxrwx root/root                 7 2024-09-16 10:00 squashfs-root/bin -> usr/bin",
+        )
+        .unwrap();
+        assert_eq!(link.path, PathBuf::from("/bin"));
+        assert_eq!(
+            link.kind,
+            EntryKind::Symlink {
+                target: PathBuf::from("usr/bin")
+            }
+        );
+
+        let dev = parse_long_line(
+            "crw-r--r-- root/root             1,  3 2024-09-16 10:00 squashfs-root/dev/null",
+        )
+        .unwrap();
+        assert_eq!(dev.kind, EntryKind::CharDevice { major: 1, minor: 3 });
+        assert_eq!(dev.size, 0);
+
+        assert!(
+            parse_long_line("drwxr-xr-x root/root 38 2024-09-16 10:00 squashfs-root").is_none()
+        );
+        assert!(parse_long_line("Parallel unsquashfs: Using 4 processors").is_none());
+    }
+
+    #[test]
+    fn test_list_cancel() {
+        let binary = fake_binary("list-slow", "echo squashfs-root/a; exec sleep 10");
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let unsquashfs_clone = unsquashfs.clone();
+
+        let t = thread::spawn(move || unsquashfs.list_long("testdata/test_extract.squashfs"));
+
+        while unsquashfs_clone.cancel().is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(matches!(t.join().unwrap(), Err(UnsquashfsError::Cancelled)));
+        assert_eq!(unsquashfs_clone.status(), Status::Cancelled);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{ffi::OsStr, marker::PhantomData, path::Path};
+
+use crate::ExtractSummary;
+
+/// Logs through the `log` crate if the `log` feature is enabled, such as
+/// `diag!(debug, "spawning {}", binary)`.
+///
+/// Without the feature, the arguments are still type checked but never
+/// evaluated.
+macro_rules! diag {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::$level!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format!($($arg)+);
+        }
+    }};
+}
+
+/// Formats a command line for logging, quoting and escaping every argument
+/// so that paths with spaces, quotes or non UTF-8 bytes stay unambiguous.
+pub(crate) fn command_line<S: AsRef<OsStr>>(binary: &Path, args: &[S]) -> String {
+    let mut line = format!("{:?}", binary.as_os_str());
+
+    for arg in args {
+        line.push_str(&format!(" {:?}", arg.as_ref()));
+    }
+
+    line
+}
+
+/// Logs what `unsquashfs` printed to stderr, if anything.
+pub(crate) fn stderr(stderr: &str) {
+    let stderr = stderr.trim_end();
+
+    if !stderr.is_empty() {
+        diag!(warn, "`unsquashfs` stderr:\n{stderr}");
+    }
+}
+
+/// A `tracing` span if the `tracing` feature is enabled, and nothing
+/// otherwise.
+#[derive(Clone)]
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    inner: tracing::Span,
+}
+
+/// Guard returned by [`Span::enter`].
+pub(crate) struct Entered<'a> {
+    #[cfg(feature = "tracing")]
+    _inner: tracing::span::Entered<'a>,
+    _span: PhantomData<&'a Span>,
+}
+
+impl Span {
+    /// The `unsquashfs_extract` span wrapping an extraction.
+    pub(crate) fn extract(archive: &Path, dest: &Path) -> Self {
+        #[cfg(not(feature = "tracing"))]
+        let _ = (archive, dest);
+
+        Self {
+            #[cfg(feature = "tracing")]
+            inner: tracing::info_span!(
+                "unsquashfs_extract",
+                archive = %archive.display(),
+                dest = %dest.display(),
+                percent = tracing::field::Empty,
+                files = tracing::field::Empty,
+                dirs = tracing::field::Empty,
+                symlinks = tracing::field::Empty,
+                devices = tracing::field::Empty,
+                bytes = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// The span the calling thread is in, to be entered by threads it spawns.
+    pub(crate) fn current() -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            inner: tracing::Span::current(),
+        }
+    }
+
+    pub(crate) fn enter(&self) -> Entered<'_> {
+        Entered {
+            #[cfg(feature = "tracing")]
+            _inner: self.inner.enter(),
+            _span: PhantomData,
+        }
+    }
+
+    pub(crate) fn record_percent(&self, percent: i32) {
+        #[cfg(feature = "tracing")]
+        self.inner.record("percent", percent);
+        #[cfg(not(feature = "tracing"))]
+        let _ = percent;
+    }
+
+    pub(crate) fn record_summary(&self, summary: &ExtractSummary) {
+        #[cfg(feature = "tracing")]
+        {
+            self.inner.record("files", summary.files);
+            self.inner.record("dirs", summary.dirs);
+            self.inner.record("symlinks", summary.symlinks);
+            self.inner.record("devices", summary.devices);
+            self.inner.record("bytes", summary.bytes);
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = summary;
+    }
+
+    /// Runs `future` inside the span.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn instrument<F: std::future::Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        return tracing::Instrument::instrument(future, self.inner.clone()).await;
+        #[cfg(not(feature = "tracing"))]
+        return future.await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt, path::Path};
+
+    use super::command_line;
+
+    #[test]
+    fn test_command_line() {
+        let args = [
+            OsString::from("-d"),
+            OsString::from("/tmp/it's here"),
+            OsString::from_vec(b"/tmp/\xff".to_vec()),
+        ];
+
+        assert_eq!(
+            command_line(Path::new("unsquashfs"), &args),
+            r#""unsquashfs" "-d" "/tmp/it's here" "/tmp/\xFF""#
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{ExtractOptions, Status, Unsquashfs, UnsquashfsError};
+
+/// What the workers of [`Unsquashfs::extract_many_parallel`] report.
+enum Message {
+    Progress(usize, i32),
+    Finished(usize, Result<(), UnsquashfsError>),
+}
+
+impl Unsquashfs {
+    /// Extracts several images one after the other, each `(archive, dest)`
+    /// job with `options` and its own destination.
+    ///
+    /// `callback` receives the index of the job and its percentage. Every job
+    /// is attempted even if an earlier one fails, and the result of each is
+    /// returned in order. [`Unsquashfs::cancel`] stops the running job and
+    /// makes the remaining ones fail with [`UnsquashfsError::Cancelled`].
+    /// They all fail with [`UnsquashfsError::Busy`] if the instance is
+    /// already running something.
+    pub fn extract_many<A, D>(
+        &self,
+        jobs: &[(A, D)],
+        options: &ExtractOptions,
+        callback: impl FnMut(usize, i32),
+    ) -> Vec<Result<(), UnsquashfsError>>
+    where
+        A: AsRef<Path> + Sync,
+        D: AsRef<Path> + Sync,
+    {
+        self.extract_many_parallel(jobs, options, 1, callback)
+    }
+
+    /// Extracts several images like [`Unsquashfs::extract_many`], running up
+    /// to `parallel` of them at once.
+    ///
+    /// [`Status::Working`] holds the mean percentage of all jobs while they
+    /// run, and cancelling stops every job in flight. If `callback` panics,
+    /// the jobs are stopped and the run ends as [`Status::Failed`] before
+    /// the panic is passed on.
+    pub fn extract_many_parallel<A, D>(
+        &self,
+        jobs: &[(A, D)],
+        options: &ExtractOptions,
+        parallel: usize,
+        mut callback: impl FnMut(usize, i32),
+    ) -> Vec<Result<(), UnsquashfsError>>
+    where
+        A: AsRef<Path> + Sync,
+        D: AsRef<Path> + Sync,
+    {
+        let Ok(claim) = self.begin() else {
+            return jobs.iter().map(|_| Err(UnsquashfsError::Busy)).collect();
+        };
+        // Ended below once every job is over.
+        claim.spawned();
+
+        // One instance per job, so that each can be cancelled on its own.
+        let workers: Vec<Unsquashfs> = jobs.iter().map(|_| self.sibling()).collect();
+        let mut results: Vec<Option<Result<(), UnsquashfsError>>> =
+            jobs.iter().map(|_| None).collect();
+        let mut percents = vec![0; jobs.len()];
+
+        let next = AtomicUsize::new(0);
+        let stopped = AtomicBool::new(false);
+        let (tx, rx) = mpsc::channel();
+        let mut panicked = None;
+
+        thread::scope(|scope| {
+            for _ in 0..parallel.clamp(1, jobs.len().max(1)) {
+                let tx = tx.clone();
+                let (next, stopped, workers) = (&next, &stopped, &workers);
+
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+
+                    if index >= jobs.len() || stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+
+                    let (archive, dest) = &jobs[index];
+                    let unsquashfs = &workers[index];
+                    let options = options.clone().dest(dest);
+
+                    let res = unsquashfs
+                        .extract_with(archive, &options, |percent| {
+                            tx.send(Message::Progress(index, percent)).ok();
+                        })
+                        .and_then(|_| match unsquashfs.status() {
+                            Status::Cancelled => Err(UnsquashfsError::Cancelled),
+                            _ => Ok(()),
+                        });
+
+                    tx.send(Message::Finished(index, res)).ok();
+                });
+            }
+
+            drop(tx);
+
+            loop {
+                match rx.recv_timeout(Duration::from_millis(10)) {
+                    Ok(Message::Progress(index, percent)) => {
+                        percents[index] = percent;
+                        self.set_mean_percent(&percents);
+
+                        if panicked.is_none() {
+                            panicked =
+                                panic::catch_unwind(AssertUnwindSafe(|| callback(index, percent)))
+                                    .err();
+                        }
+                    }
+                    Ok(Message::Finished(index, res)) => results[index] = Some(res),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+
+                // Keep cancelling, as a job may not have spawned `unsquashfs`
+                // yet the first time around.
+                if self.cancel.load(Ordering::SeqCst) || panicked.is_some() {
+                    stopped.store(true, Ordering::SeqCst);
+                    workers.iter().for_each(|worker| {
+                        worker.cancel().ok();
+                    });
+                }
+            }
+        });
+
+        let cancelled = self.cancel.swap(false, Ordering::SeqCst);
+
+        if let Some(payload) = panicked {
+            self.end(Status::Failed, cancelled);
+            panic::resume_unwind(payload);
+        }
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|res| res.unwrap_or(Err(UnsquashfsError::Cancelled)))
+            .collect();
+
+        let status = if cancelled {
+            Status::Cancelled
+        } else if results.iter().any(Result::is_err) {
+            Status::Failed
+        } else {
+            Status::Done
+        };
+        self.end(status, cancelled);
+
+        results
+    }
+
+    /// A new instance running the same binary, with its own status.
+    fn sibling(&self) -> Self {
+        Self {
+            binary: self.binary.clone(),
+            backend: self.backend,
+            help: self.help.clone(),
+            version: self.version.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn set_mean_percent(&self, percents: &[i32]) {
+        if let Status::Working { percent } = &mut *self.status.write().unwrap() {
+            *percent = percents.iter().sum::<i32>() / percents.len() as i32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs, path::PathBuf, thread, time::Duration};
+
+    use crate::{test::fake_binary, ExtractOptions, Status, Unsquashfs, UnsquashfsError};
+
+    fn dests(name: &str, count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| {
+                let dest = temp_dir().join(format!("unsqfs-wrap-test-{name}-{i}"));
+                fs::create_dir_all(&dest).unwrap();
+                dest
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_many() {
+        let binary = fake_binary(
+            "many",
+            "printf '[=====     ] 1/2  50%%\\r[==========] 2/2 100%%\\n'",
+        );
+        let dests = dests("many", 3);
+        let jobs = [
+            (PathBuf::from("testdata/test_extract.squashfs"), &dests[0]),
+            (PathBuf::from("testdata/missing.squashfs"), &dests[1]),
+            (PathBuf::from("testdata/test_extract.squashfs"), &dests[2]),
+        ];
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let mut progress = Vec::new();
+        let results =
+            unsquashfs.extract_many_parallel(&jobs, &ExtractOptions::new(), 2, |index, percent| {
+                progress.push((index, percent))
+            });
+
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(UnsquashfsError::ArchiveNotFound(_))
+        ));
+        assert!(results[2].is_ok());
+        assert!(progress.contains(&(0, 100)) && progress.contains(&(2, 100)));
+        assert!(!progress.iter().any(|(index, _)| *index == 1));
+        assert_eq!(unsquashfs.status(), Status::Failed);
+    }
+
+    #[test]
+    fn test_extract_many_cancel() {
+        let binary = fake_binary(
+            "many-slow",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+        let dests = dests("many-cancel", 3);
+        let jobs: Vec<_> = dests
+            .into_iter()
+            .map(|dest| ("testdata/test_extract.squashfs", dest))
+            .collect();
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_many_parallel(&jobs, &ExtractOptions::new(), 2, |_, _| {})
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 6.. }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        unsquashfs.cancel().unwrap();
+        let results = t.join().unwrap();
+
+        assert!(results
+            .iter()
+            .all(|res| matches!(res, Err(UnsquashfsError::Cancelled))));
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+    }
+
+    #[test]
+    fn test_extract_many_panicking_callback() {
+        let binary = fake_binary(
+            "many-panic",
+            "[ \"$1\" = -help ] && exit 0
+[ -e \"$0.fast\" ] && printf '[==========] 1/1 100%%\\n' && exit 0
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+        fs::remove_file(binary.with_extension("fast")).ok();
+        let dests = dests("many-panic", 2);
+        let jobs: Vec<_> = dests
+            .into_iter()
+            .map(|dest| ("testdata/test_extract.squashfs", dest))
+            .collect();
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let panicking_jobs = jobs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_many_parallel(
+                &panicking_jobs,
+                &ExtractOptions::new(),
+                2,
+                |_, _| panic!("the callback gave up"),
+            )
+        });
+
+        assert!(t.join().is_err());
+        assert_eq!(unsquashfs.status(), Status::Failed);
+
+        // The instance is not left busy.
+        fs::write(binary.with_extension("fast"), "").unwrap();
+        let results = unsquashfs.extract_many(&jobs, &ExtractOptions::new(), |_, _| {});
+        assert!(results.iter().all(Result::is_ok));
+    }
+}
@@ -0,0 +1,1315 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{CString, OsString},
+    fmt, io,
+    mem::MaybeUninit,
+    num::NonZeroUsize,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    denied, progress::Output, resolve_archive, Backend, IoPriority, UnsquashfsError,
+    DEFAULT_CANCEL_GRACE,
+};
+
+/// How extended attributes stored in the image are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum XattrPolicy {
+    /// Let `unsquashfs` decide, which writes all xattrs it can.
+    #[default]
+    Default,
+    /// Don't write any xattrs (`-no-xattrs`), for filesystems without
+    /// xattr support.
+    Skip,
+    /// Only write xattrs in the `user.` namespace (`-user-xattrs`), so that
+    /// extracting as a normal user doesn't trip over `security.*` and
+    /// `trusted.*` ones. Falls back to [`XattrPolicy::Skip`] if the installed
+    /// `unsquashfs` doesn't support it.
+    UserOnly,
+    /// Write all xattrs (`-xattrs`), even if the `unsquashfs` build defaults
+    /// to skipping them.
+    All,
+}
+
+/// How `unsquashfs` handles errors on individual files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorMode {
+    /// Let `unsquashfs` decide which errors are fatal.
+    #[default]
+    Default,
+    /// Abort on any error (`-strict-errors`).
+    Strict,
+    /// Carry on past errors writing files (`-ignore-errors`), for salvaging
+    /// what is readable from a damaged image. The skipped files are reported
+    /// in [`ExtractSummary::errors`](crate::ExtractSummary#structfield.errors).
+    Ignore,
+}
+
+impl ErrorMode {
+    /// Whether `unsquashfs` exiting with `status` means the extraction
+    /// succeeded.
+    pub(crate) fn succeeded(self, status: ExitStatus) -> bool {
+        // `unsquashfs` exits with 2 if it ignored any errors.
+        status.success() || (self == ErrorMode::Ignore && status.code() == Some(2))
+    }
+}
+
+/// How many processors `unsquashfs` extracts with (`-p`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threads {
+    /// Every processor available to this process, which may be fewer than
+    /// the machine has, such as in a container.
+    Auto,
+    /// Half of [`Threads::Auto`], leaving room for the rest of the system.
+    HalfCores,
+    /// Exactly this many, between 1 and [`MAX_THREADS`].
+    Fixed(usize),
+}
+
+impl Threads {
+    /// The number of processors this stands for on this machine.
+    pub fn count(self) -> usize {
+        let available = || thread::available_parallelism().map_or(1, NonZeroUsize::get);
+
+        match self {
+            Threads::Auto => available(),
+            Threads::HalfCores => (available() / 2).max(1),
+            Threads::Fixed(count) => count,
+        }
+    }
+}
+
+impl From<usize> for Threads {
+    fn from(count: usize) -> Self {
+        Threads::Fixed(count)
+    }
+}
+
+/// Largest count accepted by [`Threads::Fixed`].
+pub const MAX_THREADS: usize = 1024;
+
+/// Largest queue size accepted by [`ExtractOptions::data_queue_mb`] and
+/// [`ExtractOptions::frag_queue_mb`], as `unsquashfs` keeps the size in
+/// bytes in an `int`.
+pub const MAX_QUEUE_MB: u32 = 2047;
+
+/// Smallest memory limit accepted by [`ExtractOptions::mem_limit`], below
+/// which `unsquashfs` refuses to start.
+pub const MIN_MEM_LIMIT: u64 = 2 << 20;
+
+type StderrFn = dyn FnMut(&str) + Send;
+
+/// Callback receiving each line `unsquashfs` prints to stderr.
+#[derive(Clone)]
+pub(crate) struct OnStderr(Arc<Mutex<StderrFn>>);
+
+impl OnStderr {
+    pub(crate) fn call(&self, line: &str) {
+        (self.0.lock().unwrap())(line)
+    }
+}
+
+impl fmt::Debug for OnStderr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnStderr(..)")
+    }
+}
+
+type FileFn = dyn FnMut(&Path) + Send;
+
+/// Callback receiving the path of each file as it is extracted.
+#[derive(Clone)]
+pub(crate) struct OnFile(Arc<Mutex<FileFn>>);
+
+impl OnFile {
+    pub(crate) fn call(&self, path: &Path) {
+        (self.0.lock().unwrap())(path)
+    }
+}
+
+impl fmt::Debug for OnFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnFile(..)")
+    }
+}
+
+/// Options controlling how an image is extracted.
+///
+/// Built with chainable setters and consumed by [`Unsquashfs::extract_with`].
+///
+/// [`Unsquashfs::extract_with`]: crate::Unsquashfs::extract_with
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    pub(crate) dest: Option<PathBuf>,
+    pub(crate) threads: Option<Threads>,
+    pub(crate) force: bool,
+    pub(crate) quiet: bool,
+    pub(crate) paths: Vec<PathBuf>,
+    pub(crate) excludes: Vec<String>,
+    pub(crate) regex: bool,
+    pub(crate) offset: Option<u64>,
+    pub(crate) xattrs: XattrPolicy,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) time_limit: Option<Duration>,
+    /// Print each extracted file (`-info`), set by
+    /// [`Unsquashfs::extract_with_files`](crate::Unsquashfs::extract_with_files)
+    /// and [`ExtractOptions::on_file`].
+    pub(crate) info: bool,
+    pub(crate) cleanup_on_cancel: bool,
+    pub(crate) cancel_grace: Duration,
+    /// Print bare percentages (`-percentage`) instead of the progress bar,
+    /// for callers that don't need the inode counters. Turned off again if
+    /// the installed `unsquashfs` doesn't support it.
+    pub(crate) percentage: bool,
+    /// Don't print progress at all (`-no-progress`), set by
+    /// [`Unsquashfs::extract_no_progress`](crate::Unsquashfs::extract_no_progress).
+    /// Turned off again if the installed `unsquashfs` doesn't support it.
+    pub(crate) no_progress: bool,
+    pub(crate) on_stderr: Option<OnStderr>,
+    pub(crate) on_file: Option<OnFile>,
+    pub(crate) error_mode: ErrorMode,
+    pub(crate) data_queue_mb: Option<u32>,
+    pub(crate) frag_queue_mb: Option<u32>,
+    pub(crate) mem_limit: Option<u64>,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) nice: Option<i32>,
+    pub(crate) io_priority: Option<IoPriority>,
+    pub(crate) cgroup: Option<PathBuf>,
+    pub(crate) extra_args: Vec<OsString>,
+    pub(crate) env: BTreeMap<OsString, OsString>,
+    pub(crate) check_compression: bool,
+    pub(crate) progress_step: u8,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            dest: None,
+            threads: None,
+            force: true,
+            quiet: true,
+            paths: Vec::new(),
+            excludes: Vec::new(),
+            regex: false,
+            offset: None,
+            xattrs: XattrPolicy::Default,
+            timeout: None,
+            time_limit: None,
+            info: false,
+            cleanup_on_cancel: false,
+            cancel_grace: DEFAULT_CANCEL_GRACE,
+            percentage: false,
+            no_progress: false,
+            on_stderr: None,
+            on_file: None,
+            error_mode: ErrorMode::Default,
+            data_queue_mb: None,
+            frag_queue_mb: None,
+            mem_limit: None,
+            follow_symlinks: false,
+            nice: None,
+            io_priority: None,
+            cgroup: None,
+            extra_args: Vec::new(),
+            env: BTreeMap::new(),
+            check_compression: false,
+            progress_step: 1,
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub fn new() -> Self {
+        ExtractOptions::default()
+    }
+
+    /// Directory to extract into (`-d`), which `unsquashfs` creates if it
+    /// doesn't exist yet.
+    pub fn dest(mut self, dest: impl AsRef<Path>) -> Self {
+        self.dest = Some(dest.as_ref().to_path_buf());
+        self
+    }
+
+    /// Limit the number of processors `unsquashfs` uses (`-p`), either to a
+    /// count or to one computed from the available processors like
+    /// [`Threads::HalfCores`].
+    ///
+    /// The count used is reported in [`ExtractSummary::processors`].
+    ///
+    /// [`ExtractSummary::processors`]: crate::ExtractSummary::processors
+    pub fn threads(mut self, threads: impl Into<Threads>) -> Self {
+        self.threads = Some(threads.into());
+        self
+    }
+
+    /// Overwrite existing files in the destination (`-f`), enabled by default.
+    ///
+    /// When disabled, extracting over an existing file fails with
+    /// [`UnsquashfsError::DestinationNotEmpty`].
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Suppress non-progress output (`-q`), enabled by default.
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Only extract the given paths inside the image instead of everything.
+    pub fn paths(mut self, paths: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
+        self.paths = paths
+            .into_iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect();
+        self
+    }
+
+    /// Skip paths matching the given patterns (`-exclude-list`).
+    ///
+    /// The patterns are passed on the command line, so no temporary exclude
+    /// file is left behind on failure or cancellation. This needs
+    /// squashfs-tools 4.6 or newer.
+    ///
+    /// Patterns use the shell wildcard syntax of `unsquashfs` (`*`, `?` and
+    /// `[...]`), matched against each path component, unless
+    /// [`ExtractOptions::regex`] is enabled. There is no recursive `**`
+    /// wildcard: excluding a directory excludes everything below it.
+    pub fn exclude(mut self, patterns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.excludes = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Pass `args` to the binary after the flags set by the other options,
+    /// before the image, for flags that have no option of their own.
+    ///
+    /// They are passed as they are, each as one argument and without going
+    /// through a shell, so nothing in them is expanded or split. Whether
+    /// they make sense, for the backend and together with the other options,
+    /// is up to the caller.
+    pub fn extra_args(mut self, args: impl IntoIterator<Item = impl Into<OsString>>) -> Self {
+        self.extra_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set an environment variable for the binary, on top of the inherited
+    /// environment.
+    ///
+    /// `COLUMNS` and `LINES` are cleared by default so that the progress bar
+    /// is as wide as the pseudo-terminal it is read from rather than the
+    /// terminal of the caller; setting them here overrides that.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Treat [`ExtractOptions::paths`] and [`ExtractOptions::exclude`] as
+    /// POSIX regular expressions instead of wildcards (`-regex`).
+    ///
+    /// This is not turned on by looking at the patterns: `*`, `?` and `[`
+    /// mean something in both syntaxes, and the characters only regular
+    /// expressions use, such as `+` or `(`, also appear in plain file names
+    /// like `libstdc++.so.6`, which would then stop matching.
+    pub fn regex(mut self, regex: bool) -> Self {
+        self.regex = regex;
+        self
+    }
+
+    /// Read the image starting at a byte offset inside `archive` (`-offset`),
+    /// for images embedded in a larger file.
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// How to handle extended attributes, see [`XattrPolicy`].
+    ///
+    /// With [`XattrPolicy::Skip`] and [`XattrPolicy::UserOnly`], xattr
+    /// warnings are also left out of the output in
+    /// [`UnsquashfsError::Failure`].
+    pub fn xattrs(mut self, xattrs: XattrPolicy) -> Self {
+        self.xattrs = xattrs;
+        self
+    }
+
+    /// Kill `unsquashfs` if it reports no progress for `timeout`, making the
+    /// extraction fail with [`UnsquashfsError::Timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kill `unsquashfs` once it has been running for `limit`, progress or
+    /// not, making the extraction fail with [`UnsquashfsError::TimedOut`].
+    ///
+    /// Time spent paused counts towards the limit.
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    /// Only call the progress callback once the percentage moved by at least
+    /// `step`, from 1 to 100, since the last call, to spare redraws to UIs
+    /// that don't need every percent. 0 and 100 are always reported.
+    ///
+    /// Defaults to 1, reporting every change. [`Unsquashfs::status`] is
+    /// still updated every time.
+    ///
+    /// [`Unsquashfs::status`]: crate::Unsquashfs::status
+    pub fn progress_step(mut self, step: u8) -> Self {
+        self.progress_step = step;
+        self
+    }
+
+    /// Read the superblock of the image (`-s`) before extracting it, and fail
+    /// right away with [`UnsquashfsError::UnsupportedCompression`] if its
+    /// compressor is not among the decompressors listed by `-help`.
+    ///
+    /// This runs `unsquashfs` once more for every extraction, or twice the
+    /// first time, and is skipped with the `rdsquashfs` backend and with
+    /// builds that don't list their decompressors.
+    pub fn check_compression(mut self, check: bool) -> Self {
+        self.check_compression = check;
+        self
+    }
+
+    /// Run `unsquashfs` with the niceness `nice`, from -20 to 19, so that an
+    /// extraction in the background doesn't slow down the rest of the
+    /// system. Only the spawned process is affected.
+    ///
+    /// Going below the niceness of the calling process needs privileges,
+    /// otherwise spawning fails.
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Run `unsquashfs` in the I/O scheduling class `priority`, like
+    /// `ionice`. Only the spawned process is affected, and this does nothing
+    /// on platforms other than Linux.
+    pub fn io_priority(mut self, priority: IoPriority) -> Self {
+        self.io_priority = Some(priority);
+        self
+    }
+
+    /// Run `unsquashfs` in the existing cgroup v2 directory `cgroup`, such as
+    /// `/sys/fs/cgroup/installer`, so that its `memory.max` and `io.max`
+    /// apply. The process joins it before it execs `unsquashfs`.
+    ///
+    /// This fails with [`UnsquashfsError::CgroupDenied`] if the caller may
+    /// not move processes into it.
+    pub fn cgroup(mut self, cgroup: impl AsRef<Path>) -> Self {
+        self.cgroup = Some(cgroup.as_ref().to_path_buf());
+        self
+    }
+
+    /// How long `unsquashfs` has to exit after `SIGTERM` when cancelled or
+    /// timed out before it is killed, [`DEFAULT_CANCEL_GRACE`] by default.
+    ///
+    /// A zero grace period kills it right away.
+    pub fn cancel_grace(mut self, grace: Duration) -> Self {
+        self.cancel_grace = grace;
+        self
+    }
+
+    /// Remove what was extracted if the extraction is cancelled or times out.
+    ///
+    /// Only files and directories that did not exist in the destination
+    /// before the extraction started are removed.
+    pub fn cleanup_on_cancel(mut self, cleanup: bool) -> Self {
+        self.cleanup_on_cancel = cleanup;
+        self
+    }
+
+    /// Call `on_stderr` with each line `unsquashfs` prints to stderr as soon
+    /// as it is printed, such as warnings during an extraction that still
+    /// succeeds.
+    ///
+    /// [`UnsquashfsError::Failure`] still contains the whole output.
+    pub fn on_stderr(mut self, on_stderr: impl FnMut(&str) + Send + 'static) -> Self {
+        self.on_stderr = Some(OnStderr(Arc::new(Mutex::new(on_stderr))));
+        self
+    }
+
+    /// Call `on_file` with the path inside the image of each file as it is
+    /// written, such as `/etc/os-release` (`-info`), alongside the progress
+    /// callback of whichever extraction method is used.
+    ///
+    /// `-q` is not passed even if [`ExtractOptions::quiet`] is set, as it
+    /// hides the file names.
+    pub fn on_file(mut self, on_file: impl FnMut(&Path) + Send + 'static) -> Self {
+        self.on_file = Some(OnFile(Arc::new(Mutex::new(on_file))));
+        self.info = true;
+        self
+    }
+
+    /// How to handle errors on individual files, see [`ErrorMode`].
+    pub fn error_mode(mut self, error_mode: ErrorMode) -> Self {
+        self.error_mode = error_mode;
+        self
+    }
+
+    /// Size of the queue of data blocks waiting to be written, in MiB
+    /// (`-data-queue`). `unsquashfs` defaults to 256 MiB.
+    ///
+    /// A larger queue helps when the destination is fast enough that
+    /// `unsquashfs` stalls on decompression, such as NVMe drives with many
+    /// processors available, at the cost of memory.
+    pub fn data_queue_mb(mut self, size: u32) -> Self {
+        self.data_queue_mb = Some(size);
+        self
+    }
+
+    /// Size of the fragment cache, in MiB (`-frag-queue`). `unsquashfs`
+    /// defaults to 256 MiB.
+    ///
+    /// Images with many small files packed into fragments benefit the most
+    /// from a larger cache.
+    pub fn frag_queue_mb(mut self, size: u32) -> Self {
+        self.frag_queue_mb = Some(size);
+        self
+    }
+
+    /// Limit the memory `unsquashfs` uses for its caches to `bytes` (`-mem`).
+    /// `unsquashfs` defaults to 512 MiB.
+    ///
+    /// The limit is rounded down to whole KiB and must be at least
+    /// [`MIN_MEM_LIMIT`]. This needs squashfs-tools 4.6 or newer.
+    pub fn mem_limit(mut self, bytes: u64) -> Self {
+        self.mem_limit = Some(bytes);
+        self
+    }
+
+    /// Follow symlinks among and leading to [`ExtractOptions::paths`]
+    /// (`-follow-symlinks`), also extracting what they point to inside the
+    /// image. Disabled by default.
+    ///
+    /// This only matters when extracting selected paths. It turns off
+    /// wildcard matching, so the paths and [`ExtractOptions::exclude`]
+    /// patterns are taken literally, and can't be combined with
+    /// [`ExtractOptions::regex`]. This needs squashfs-tools 4.6 or newer.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// The parser for the output of an extraction with these options.
+    pub(crate) fn output(&self) -> Result<Output, UnsquashfsError> {
+        let output = match &self.dest {
+            _ if !self.info => Output::default(),
+            Some(dest) => Output::with_files(resolve_dest(dest).map_err(denied(Some(dest)))?),
+            None => Output::with_files(self.dest_dir().to_path_buf()),
+        };
+
+        Ok(output.with_percentage(self.percentage))
+    }
+
+    /// Directory `unsquashfs` extracts into.
+    pub(crate) fn dest_dir(&self) -> &Path {
+        self.dest.as_deref().unwrap_or(Path::new("squashfs-root"))
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), UnsquashfsError> {
+        if self.threads == Some(Threads::Fixed(0)) {
+            return Err(UnsquashfsError::InvalidOption(
+                "thread count must be greater than 0".to_string(),
+            ));
+        }
+
+        if self
+            .threads
+            .is_some_and(|threads| threads.count() > MAX_THREADS)
+        {
+            return Err(UnsquashfsError::InvalidOption(format!(
+                "thread count must be at most {MAX_THREADS}"
+            )));
+        }
+
+        if self.timeout == Some(Duration::ZERO) {
+            return Err(UnsquashfsError::InvalidOption(
+                "timeout must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.no_progress && self.timeout.is_some() {
+            return Err(UnsquashfsError::InvalidOption(
+                "timeout needs progress to be reported".to_string(),
+            ));
+        }
+
+        if !(1..=100).contains(&self.progress_step) {
+            return Err(UnsquashfsError::InvalidOption(
+                "progress step must be from 1 to 100".to_string(),
+            ));
+        }
+
+        if self.time_limit == Some(Duration::ZERO) {
+            return Err(UnsquashfsError::InvalidOption(
+                "time limit must be greater than 0".to_string(),
+            ));
+        }
+
+        for (name, size) in [
+            ("data queue", self.data_queue_mb),
+            ("fragment queue", self.frag_queue_mb),
+        ] {
+            if size.is_some_and(|size| !(1..=MAX_QUEUE_MB).contains(&size)) {
+                return Err(UnsquashfsError::InvalidOption(format!(
+                    "{name} size must be between 1 and {MAX_QUEUE_MB} MiB"
+                )));
+            }
+        }
+
+        if let Some(bytes) = self.mem_limit {
+            if bytes < MIN_MEM_LIMIT {
+                return Err(UnsquashfsError::InvalidOption(format!(
+                    "memory limit of {bytes} bytes is below the minimum of {MIN_MEM_LIMIT} bytes"
+                )));
+            }
+        }
+
+        if self.follow_symlinks && self.regex {
+            return Err(UnsquashfsError::InvalidOption(
+                "following symlinks can't be combined with regex".to_string(),
+            ));
+        }
+
+        for path in &self.paths {
+            if path.components().any(|c| c == Component::ParentDir) {
+                return Err(UnsquashfsError::InvalidOption(format!(
+                    "path `{}` must not contain `..`",
+                    path.display()
+                )));
+            }
+        }
+
+        for pattern in &self.excludes {
+            // `;` terminates the exclude list and `**` never matches anything.
+            if pattern.is_empty() || pattern == ";" || (!self.regex && pattern.contains("**")) {
+                return Err(UnsquashfsError::InvalidOption(format!(
+                    "unsupported exclude pattern `{pattern}`"
+                )));
+            }
+        }
+
+        if let Some(nice) = self.nice.filter(|nice| !(-20..=19).contains(nice)) {
+            return Err(UnsquashfsError::InvalidOption(format!(
+                "niceness {nice} must be between -20 and 19"
+            )));
+        }
+
+        if let Some(priority) = self.io_priority {
+            priority.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops stderr lines the caller asked not to hear about.
+    pub(crate) fn filter_stderr(&self, stderr: String) -> String {
+        if !matches!(self.xattrs, XattrPolicy::Skip | XattrPolicy::UserOnly) {
+            return stderr;
+        }
+
+        stderr
+            .lines()
+            .filter(|line| !line.contains("xattr"))
+            .map(|line| format!("{line}\n"))
+            .collect()
+    }
+
+    /// Flags passed to `unsquashfs` that older versions may not understand.
+    pub(crate) fn required_flags(&self) -> Vec<&'static str> {
+        let mut flags = self.filter_flags();
+
+        if self.mem_limit.is_some() {
+            flags.push("-mem");
+        }
+
+        // `-user-xattrs` falls back to skipping them instead.
+        match self.xattrs {
+            XattrPolicy::Skip => flags.push("-no-xattrs"),
+            XattrPolicy::All => flags.push("-xattrs"),
+            XattrPolicy::Default | XattrPolicy::UserOnly => {}
+        }
+
+        match self.error_mode {
+            ErrorMode::Default => {}
+            ErrorMode::Strict => flags.push("-strict-errors"),
+            ErrorMode::Ignore => flags.push("-ignore-errors"),
+        }
+
+        flags
+    }
+
+    /// The part of [`ExtractOptions::required_flags`] for the flags from
+    /// [`ExtractOptions::push_filter_args`].
+    pub(crate) fn filter_flags(&self) -> Vec<&'static str> {
+        let mut flags = Vec::new();
+
+        if self.regex {
+            flags.push("-regex");
+        }
+
+        if !self.excludes.is_empty() {
+            flags.push("-exclude-list");
+        }
+
+        if self.offset.is_some() {
+            flags.push("-offset");
+        }
+
+        if self.follow_symlinks {
+            flags.push("-follow-symlinks");
+        }
+
+        flags
+    }
+
+    /// Pushes the flags choosing what is read from the image, which listing
+    /// takes as well as extracting.
+    pub(crate) fn push_filter_args(&self, args: &mut Vec<OsString>) {
+        if let Some(offset) = self.offset {
+            args.push("-offset".into());
+            args.push(offset.to_string().into());
+        }
+
+        if self.regex {
+            args.push("-regex".into());
+        }
+
+        if self.follow_symlinks {
+            args.push("-follow-symlinks".into());
+        }
+
+        if !self.excludes.is_empty() {
+            args.push("-exclude-list".into());
+            args.extend(self.excludes.iter().map(OsString::from));
+            args.push(";".into());
+        }
+    }
+
+    /// The paths to extract, as `unsquashfs` takes them after the image.
+    pub(crate) fn path_args(&self) -> impl Iterator<Item = OsString> + '_ {
+        self.paths.iter().map(|path| normalize(path))
+    }
+
+    /// Builds the command line for extracting `archive` with `backend`.
+    pub(crate) fn args(
+        &self,
+        archive: &Path,
+        backend: Backend,
+    ) -> Result<Vec<OsString>, UnsquashfsError> {
+        let archive = resolve_archive(archive)?;
+        let mut args: Vec<OsString> = Vec::new();
+
+        if let Some(offset) = self.offset {
+            check_offset(&archive, offset)?;
+        }
+
+        if backend == Backend::Rdsquashfs {
+            if self.offset.is_some() {
+                return Err(UnsquashfsError::Unsupported(
+                    "offset with Rdsquashfs backend".to_string(),
+                ));
+            }
+
+            if !self.excludes.is_empty() || self.regex {
+                return Err(UnsquashfsError::Unsupported(
+                    "exclude patterns with Rdsquashfs backend".to_string(),
+                ));
+            }
+
+            if self.info {
+                return Err(UnsquashfsError::Unsupported(
+                    "per-file progress with Rdsquashfs backend".to_string(),
+                ));
+            }
+
+            if self.follow_symlinks {
+                return Err(UnsquashfsError::Unsupported(
+                    "following symlinks with Rdsquashfs backend".to_string(),
+                ));
+            }
+
+            if self.mem_limit.is_some() {
+                return Err(UnsquashfsError::Unsupported(
+                    "memory limit with Rdsquashfs backend".to_string(),
+                ));
+            }
+
+            if self.data_queue_mb.is_some() || self.frag_queue_mb.is_some() {
+                return Err(UnsquashfsError::Unsupported(
+                    "queue sizes with Rdsquashfs backend".to_string(),
+                ));
+            }
+
+            if self.error_mode != ErrorMode::Default {
+                return Err(UnsquashfsError::Unsupported(
+                    "error modes with Rdsquashfs backend".to_string(),
+                ));
+            }
+
+            // `rdsquashfs` always overwrites and has no thread limit.
+            args.push("-q".into());
+
+            // It skips xattrs unless asked to set them.
+            if self.xattrs == XattrPolicy::All {
+                args.push("-X".into());
+            }
+
+            if self.paths.is_empty() {
+                args.extend(["-u".into(), "/".into()]);
+            }
+
+            for path in &self.paths {
+                args.push("-u".into());
+                args.push(path.into());
+            }
+
+            if let Some(directory) = &self.dest {
+                args.push("-p".into());
+                args.push(
+                    resolve_dest(directory)
+                        .map_err(denied(Some(directory)))?
+                        .into(),
+                );
+            }
+
+            args.extend(self.extra_args.iter().cloned());
+            args.push(archive.into());
+
+            return Ok(args);
+        }
+
+        if let Some(threads) = self.threads {
+            args.push("-p".into());
+            args.push(threads.count().to_string().into());
+        }
+
+        if let Some(size) = self.data_queue_mb {
+            args.push("-data-queue".into());
+            args.push(size.to_string().into());
+        }
+
+        if let Some(size) = self.frag_queue_mb {
+            args.push("-frag-queue".into());
+            args.push(size.to_string().into());
+        }
+
+        if let Some(bytes) = self.mem_limit {
+            args.push("-mem".into());
+            args.push(format_size(bytes).into());
+        }
+
+        if self.force {
+            args.push("-f".into());
+        }
+
+        if self.quiet && !self.info {
+            args.push("-q".into());
+        }
+
+        if self.info {
+            args.push("-info".into());
+        }
+
+        if self.percentage {
+            args.push("-percentage".into());
+        }
+
+        if self.no_progress {
+            args.push("-no-progress".into());
+        }
+
+        if let Some(directory) = &self.dest {
+            args.push("-d".into());
+            args.push(
+                resolve_dest(directory)
+                    .map_err(denied(Some(directory)))?
+                    .into(),
+            );
+        }
+
+        match self.xattrs {
+            XattrPolicy::Default => {}
+            XattrPolicy::Skip => args.push("-no-xattrs".into()),
+            XattrPolicy::UserOnly => args.push("-user-xattrs".into()),
+            XattrPolicy::All => args.push("-xattrs".into()),
+        }
+
+        match self.error_mode {
+            ErrorMode::Default => {}
+            ErrorMode::Strict => args.push("-strict-errors".into()),
+            ErrorMode::Ignore => args.push("-ignore-errors".into()),
+        }
+
+        self.push_filter_args(&mut args);
+        args.extend(self.extra_args.iter().cloned());
+
+        args.push(archive.into());
+        args.extend(self.path_args());
+
+        Ok(args)
+    }
+}
+
+/// Checks that `offset` points inside `archive`, which gives a clearer error
+/// than `unsquashfs` failing to find a superblock.
+pub(crate) fn check_offset(archive: &Path, offset: u64) -> Result<(), UnsquashfsError> {
+    let len = archive.metadata()?.len();
+
+    if offset >= len {
+        return Err(UnsquashfsError::InvalidOption(format!(
+            "offset {offset} is beyond the end of the archive ({len} bytes)"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Makes a destination directory absolute like [`Path::canonicalize`], even
+/// if it doesn't exist yet, in which case `unsquashfs` creates it, by
+/// resolving its nearest existing ancestor and appending the rest.
+pub(crate) fn resolve_dest(dest: &Path) -> io::Result<PathBuf> {
+    match dest.canonicalize() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let (Some(parent), Some(name)) = (dest.parent(), dest.file_name()) else {
+                return Err(e);
+            };
+
+            match parent.as_os_str().is_empty() {
+                true => resolve_dest(Path::new(".")),
+                false => resolve_dest(parent),
+            }
+            .map(|parent| parent.join(name))
+        }
+        res => res,
+    }
+}
+
+/// Checks that `unsquashfs` can write to `dest`, or to its nearest existing
+/// ancestor if it is yet to be created, and that there is some room left
+/// there.
+pub(crate) fn check_dest(dest: &Path) -> Result<(), UnsquashfsError> {
+    let resolved = resolve_dest(dest).map_err(denied(Some(dest)))?;
+    let Some(existing) = resolved.ancestors().find(|path| path.exists()) else {
+        return Ok(());
+    };
+    let existing = CString::new(existing.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // SAFETY: `existing` is a valid NUL-terminated string.
+    if unsafe { libc::access(existing.as_ptr(), libc::W_OK) } == -1 {
+        let e = io::Error::last_os_error();
+
+        return Err(match e.raw_os_error() {
+            Some(libc::EACCES | libc::EPERM | libc::EROFS) => {
+                UnsquashfsError::DestinationNotWritable(dest.to_path_buf())
+            }
+            _ => e.into(),
+        });
+    }
+
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `existing` is a valid NUL-terminated string and `stat` is
+    // only read if statvfs filled it.
+    if unsafe { libc::statvfs(existing.as_ptr(), stat.as_mut_ptr()) } == 0
+        && unsafe { stat.assume_init() }.f_bavail == 0
+    {
+        return Err(UnsquashfsError::DiskFull {
+            destination: dest.to_path_buf(),
+            bytes_written_estimate: Some(0),
+            stderr: None,
+        });
+    }
+
+    Ok(())
+}
+
+/// Formats a size in bytes with the largest suffix `unsquashfs` accepts that
+/// represents it exactly, rounding down to KiB otherwise.
+fn format_size(bytes: u64) -> String {
+    if bytes.is_multiple_of(1 << 30) {
+        format!("{}G", bytes >> 30)
+    } else if bytes.is_multiple_of(1 << 20) {
+        format!("{}M", bytes >> 20)
+    } else {
+        format!("{}K", bytes >> 10)
+    }
+}
+
+/// Turns an in-image path such as `/boot/./grub` into the `boot/grub` form
+/// `unsquashfs` matches extract names against.
+fn normalize(path: &Path) -> OsString {
+    let path: PathBuf = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+
+    if path.as_os_str().is_empty() {
+        "/".into()
+    } else {
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env::{current_dir, temp_dir},
+        ffi::OsString,
+        io::ErrorKind,
+        path::Path,
+    };
+
+    use super::{format_size, resolve_dest, Threads, MAX_QUEUE_MB, MAX_THREADS, MIN_MEM_LIMIT};
+    use crate::{
+        Backend, ErrorMode, ExtractOptions, IoPriority, Unsquashfs, UnsquashfsError, XattrPolicy,
+    };
+
+    #[test]
+    fn test_zero_threads_rejected() {
+        let options = ExtractOptions::new().threads(0);
+        let err = Unsquashfs::new()
+            .extract_with("testdata/test_extract.squashfs", &options, |_| {})
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+
+        let options = ExtractOptions::new().threads(MAX_THREADS + 1);
+        assert!(matches!(
+            options.validate(),
+            Err(UnsquashfsError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn test_threads() {
+        let available = std::thread::available_parallelism().unwrap().get();
+
+        assert_eq!(Threads::Auto.count(), available);
+        assert_eq!(Threads::HalfCores.count(), (available / 2).max(1));
+        assert_eq!(Threads::from(3), Threads::Fixed(3));
+
+        let args = ExtractOptions::new()
+            .threads(Threads::Auto)
+            .args(
+                Path::new("testdata/test_extract.squashfs"),
+                Backend::Unsquashfs,
+            )
+            .unwrap();
+        assert_eq!(
+            args[..2],
+            [OsString::from("-p"), OsString::from(available.to_string())]
+        );
+    }
+
+    #[test]
+    fn test_paths_args() {
+        let archive = Path::new("testdata/test_extract.squashfs");
+        let options = ExtractOptions::new()
+            .dest(temp_dir())
+            .paths(["/boot", "etc//./os-release"]);
+
+        let args = options.args(archive, Backend::Unsquashfs).unwrap();
+        let expected: [OsString; 7] = [
+            "-f".into(),
+            "-q".into(),
+            "-d".into(),
+            temp_dir().canonicalize().unwrap().into(),
+            archive.canonicalize().unwrap().into(),
+            "boot".into(),
+            "etc/os-release".into(),
+        ];
+
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn test_resolve_missing_dest() {
+        let tmp = temp_dir().canonicalize().unwrap();
+
+        assert_eq!(resolve_dest(&temp_dir()).unwrap(), tmp);
+        assert_eq!(
+            resolve_dest(&temp_dir().join("unsqfs-wrap-missing/./a/../root"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            resolve_dest(&temp_dir().join("unsqfs-wrap-missing/root")).unwrap(),
+            tmp.join("unsqfs-wrap-missing/root")
+        );
+        assert_eq!(
+            resolve_dest(Path::new("squashfs-root")).unwrap(),
+            current_dir().unwrap().join("squashfs-root")
+        );
+    }
+
+    #[test]
+    fn test_offset_beyond_archive_rejected() {
+        let archive = Path::new("testdata/test_extract.squashfs");
+        let len = archive.metadata().unwrap().len();
+
+        let args = ExtractOptions::new()
+            .offset(len - 1)
+            .args(archive, Backend::Unsquashfs)
+            .unwrap();
+        assert_eq!(
+            args[2..4],
+            [
+                OsString::from("-offset"),
+                OsString::from((len - 1).to_string())
+            ]
+        );
+
+        let err = ExtractOptions::new()
+            .offset(len)
+            .args(archive, Backend::Unsquashfs)
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+    }
+
+    #[test]
+    fn test_parent_dir_path_rejected() {
+        let err = ExtractOptions::new()
+            .paths(["../etc"])
+            .validate()
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+    }
+
+    #[test]
+    fn test_exclude_args() {
+        let archive = Path::new("testdata/test_extract.squashfs");
+        let options = ExtractOptions::new()
+            .threads(2)
+            .exclude(["var/cache", "usr/share/doc/*"]);
+
+        let args = options.args(archive, Backend::Unsquashfs).unwrap();
+        let expected: [OsString; 9] = [
+            "-p".into(),
+            "2".into(),
+            "-f".into(),
+            "-q".into(),
+            "-exclude-list".into(),
+            "var/cache".into(),
+            "usr/share/doc/*".into(),
+            ";".into(),
+            archive.canonicalize().unwrap().into(),
+        ];
+
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn test_invalid_exclude_rejected() {
+        for pattern in ["", ";", "usr/**/*.a"] {
+            let err = ExtractOptions::new()
+                .exclude([pattern])
+                .validate()
+                .unwrap_err();
+            assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+        }
+
+        assert!(ExtractOptions::new()
+            .exclude(["usr/.*\\.a"])
+            .regex(true)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_skip_xattrs() {
+        let options = ExtractOptions::new().xattrs(XattrPolicy::Skip);
+        let args = options
+            .args(
+                Path::new("testdata/test_extract.squashfs"),
+                Backend::Unsquashfs,
+            )
+            .unwrap();
+        assert!(args.contains(&OsString::from("-no-xattrs")));
+
+        let stderr = "write_xattr: could not write xattr security.selinux for file a\nFATAL ERROR: out of memory\n";
+        assert_eq!(
+            options.filter_stderr(stderr.to_string()),
+            "FATAL ERROR: out of memory\n"
+        );
+    }
+
+    #[test]
+    fn test_user_xattrs_args() {
+        let args = ExtractOptions::new()
+            .xattrs(XattrPolicy::UserOnly)
+            .args(
+                Path::new("testdata/test_extract.squashfs"),
+                Backend::Unsquashfs,
+            )
+            .unwrap();
+
+        assert!(args.contains(&OsString::from("-user-xattrs")));
+        assert!(!args.contains(&OsString::from("-no-xattrs")));
+    }
+
+    #[test]
+    fn test_all_xattrs() {
+        let options = ExtractOptions::new().xattrs(XattrPolicy::All);
+        let args = options
+            .args(
+                Path::new("testdata/test_extract.squashfs"),
+                Backend::Unsquashfs,
+            )
+            .unwrap();
+
+        assert!(args.contains(&OsString::from("-xattrs")));
+        assert_eq!(options.required_flags(), ["-xattrs"]);
+
+        // Warnings matter when all xattrs were asked for.
+        let stderr = "write_xattr: could not write xattr security.selinux for file a\n";
+        assert_eq!(options.filter_stderr(stderr.to_string()), stderr);
+    }
+
+    #[test]
+    fn test_error_mode_args() {
+        let archive = Path::new("testdata/test_extract.squashfs");
+        let options = ExtractOptions::new().error_mode(ErrorMode::Ignore);
+
+        let args = options.args(archive, Backend::Unsquashfs).unwrap();
+        assert!(args.contains(&OsString::from("-ignore-errors")));
+        assert_eq!(options.required_flags(), ["-ignore-errors"]);
+
+        let options = ExtractOptions::new().error_mode(ErrorMode::Strict);
+        assert_eq!(options.required_flags(), ["-strict-errors"]);
+        assert!(matches!(
+            options.args(archive, Backend::Rdsquashfs),
+            Err(UnsquashfsError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_queue_sizes() {
+        let args = ExtractOptions::new()
+            .threads(4)
+            .data_queue_mb(1024)
+            .frag_queue_mb(512)
+            .args(
+                Path::new("testdata/test_extract.squashfs"),
+                Backend::Unsquashfs,
+            )
+            .unwrap();
+        assert_eq!(
+            args[2..6],
+            [
+                OsString::from("-data-queue"),
+                OsString::from("1024"),
+                OsString::from("-frag-queue"),
+                OsString::from("512"),
+            ]
+        );
+
+        for options in [
+            ExtractOptions::new().data_queue_mb(0),
+            ExtractOptions::new().frag_queue_mb(MAX_QUEUE_MB + 1),
+        ] {
+            assert!(matches!(
+                options.validate(),
+                Err(UnsquashfsError::InvalidOption(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_mem_limit() {
+        assert_eq!(format_size(1 << 30), "1G");
+        assert_eq!(format_size(256 << 20), "256M");
+        assert_eq!(format_size((3 << 20) + 1536), "3073K");
+
+        let options = ExtractOptions::new().mem_limit(256 << 20);
+        let args = options
+            .args(
+                Path::new("testdata/test_extract.squashfs"),
+                Backend::Unsquashfs,
+            )
+            .unwrap();
+        assert_eq!(args[..2], [OsString::from("-mem"), OsString::from("256M")]);
+        assert_eq!(options.required_flags(), ["-mem"]);
+
+        let err = ExtractOptions::new()
+            .mem_limit(MIN_MEM_LIMIT - 1)
+            .validate()
+            .unwrap_err();
+        assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+    }
+
+    #[test]
+    fn test_follow_symlinks() {
+        let archive = Path::new("testdata/test_extract.squashfs");
+        let options = ExtractOptions::new()
+            .paths(["/usr/lib/os-release"])
+            .follow_symlinks(true);
+
+        let args = options.args(archive, Backend::Unsquashfs).unwrap();
+        let expected: [OsString; 5] = [
+            "-f".into(),
+            "-q".into(),
+            "-follow-symlinks".into(),
+            archive.canonicalize().unwrap().into(),
+            "usr/lib/os-release".into(),
+        ];
+        assert_eq!(args, expected);
+        assert_eq!(options.required_flags(), ["-follow-symlinks"]);
+
+        let err = options.regex(true).validate().unwrap_err();
+        assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+    }
+
+    #[test]
+    fn test_extra_args() {
+        let archive = Path::new("testdata/test_extract.squashfs");
+        let options = ExtractOptions::new()
+            .paths(["/etc"])
+            .exclude(["etc/shadow"])
+            .extra_args(["-max-depth", "2", "$(rm -rf ~) *"]);
+
+        let args = options.args(archive, Backend::Unsquashfs).unwrap();
+        let expected: [OsString; 10] = [
+            "-f".into(),
+            "-q".into(),
+            "-exclude-list".into(),
+            "etc/shadow".into(),
+            ";".into(),
+            "-max-depth".into(),
+            "2".into(),
+            "$(rm -rf ~) *".into(),
+            archive.canonicalize().unwrap().into(),
+            "etc".into(),
+        ];
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn test_priority_range() {
+        assert!(ExtractOptions::new().nice(19).validate().is_ok());
+        assert!(ExtractOptions::new()
+            .io_priority(IoPriority::BestEffort(7))
+            .validate()
+            .is_ok());
+
+        for options in [
+            ExtractOptions::new().nice(20),
+            ExtractOptions::new().nice(-21),
+            ExtractOptions::new().io_priority(IoPriority::BestEffort(8)),
+        ] {
+            let err = options.validate().unwrap_err();
+            assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use std::{
+    ffi::OsStr,
+    io::{self, BufReader, Read},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::ChildStdout,
+    str,
+    time::{Duration, Instant},
+};
+
+use crate::ExtractSummary;
+
+/// Progress of a running extraction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Progress {
+    pub percent: i32,
+    /// Number of inodes written so far, if `unsquashfs` reported it.
+    pub done: Option<u64>,
+    /// Total number of inodes to write, if `unsquashfs` reported it.
+    pub total: Option<u64>,
+}
+
+/// What happened during an extraction, see
+/// [`Unsquashfs::extract_with_events`](crate::Unsquashfs::extract_with_events).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProgressEvent {
+    /// `unsquashfs` was spawned.
+    Started,
+    /// The percentage changed.
+    Progress { percent: u8 },
+    /// `unsquashfs` exited successfully.
+    Finished,
+    /// The extraction was cancelled with [`Unsquashfs::cancel`](crate::Unsquashfs::cancel).
+    Cancelled,
+}
+
+/// Progress of a running extraction, with an estimate of how long is left.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ProgressStats {
+    pub percent: i32,
+    /// Smoothed extraction rate, in inodes per second when `unsquashfs`
+    /// reports inode counters and in percentage points per second otherwise.
+    pub rate_per_sec: f64,
+    /// Time since the first progress update.
+    pub elapsed: Duration,
+    /// Estimated time remaining, once there are [`MIN_ESTIMATE_TIME`] worth
+    /// of updates to estimate from.
+    pub eta: Option<Duration>,
+}
+
+/// Adapts a [`ProgressStats`] callback for use with
+/// [`Unsquashfs::extract_with_progress`](crate::Unsquashfs::extract_with_progress),
+/// timing each update to estimate the rate and time remaining.
+pub fn with_stats(mut callback: impl FnMut(ProgressStats)) -> impl FnMut(Progress) {
+    let mut estimator = RateEstimator::default();
+
+    move |progress| callback(estimator.update(progress, Instant::now()))
+}
+
+/// Weight of the newest sample in the moving average of the rate.
+const SMOOTHING: f64 = 0.3;
+
+/// How long [`ProgressStats::eta`] stays `None` after the first update.
+pub const MIN_ESTIMATE_TIME: Duration = Duration::from_secs(3);
+
+/// Exponential moving average of the extraction rate, so that bursty
+/// updates don't make the estimate jump around.
+#[derive(Default)]
+struct RateEstimator {
+    start: Option<Instant>,
+    last: Option<(f64, Instant)>,
+    rate: Option<f64>,
+}
+
+impl RateEstimator {
+    fn update(&mut self, progress: Progress, now: Instant) -> ProgressStats {
+        let (done, total) = match (progress.done, progress.total) {
+            (Some(done), Some(total)) => (done as f64, total as f64),
+            _ => (f64::from(progress.percent), 100.0),
+        };
+
+        if let Some((last_done, last_time)) = self.last {
+            let elapsed = now.duration_since(last_time).as_secs_f64();
+
+            if elapsed > 0.0 && done >= last_done {
+                let sample = (done - last_done) / elapsed;
+
+                self.rate = Some(match self.rate {
+                    Some(rate) => SMOOTHING * sample + (1.0 - SMOOTHING) * rate,
+                    None => sample,
+                });
+            }
+        }
+
+        self.last = Some((done, now));
+
+        let elapsed = now.duration_since(*self.start.get_or_insert(now));
+        let rate = self.rate.unwrap_or(0.0);
+        let eta = (rate > 0.0 && elapsed >= MIN_ESTIMATE_TIME)
+            .then(|| Duration::from_secs_f64((total - done).max(0.0) / rate));
+
+        ProgressStats {
+            percent: progress.percent,
+            rate_per_sec: rate,
+            elapsed,
+            eta,
+        }
+    }
+}
+
+impl Progress {
+    /// The update that completes an extraction whose last update was `self`,
+    /// if that didn't already reach 100%.
+    pub(crate) fn completed(self) -> Option<Self> {
+        (self.percent < 100).then_some(Self {
+            percent: 100,
+            done: self.total,
+            total: self.total,
+        })
+    }
+}
+
+/// Adapts a percentage callback, only calling it when the percentage changes.
+pub(crate) fn percent_only(mut callback: impl FnMut(i32)) -> impl FnMut(Progress) {
+    let mut last_percent = None;
+
+    move |progress| {
+        if last_percent != Some(progress.percent) {
+            callback(progress.percent);
+            last_percent = Some(progress.percent);
+        }
+    }
+}
+
+/// Adapts a progress callback, only calling it once the percentage moved by
+/// at least `step` since the last call, or when it reaches 0 or 100.
+pub(crate) fn stepped(step: u8, mut callback: impl FnMut(Progress)) -> impl FnMut(Progress) {
+    // `None` until something is reported, so that a first 0 is too.
+    let mut last_percent = None;
+
+    move |progress| {
+        let moved = (progress.percent - last_percent.unwrap_or(0)).abs() >= i32::from(step);
+        let bound = matches!(progress.percent, 0 | 100) && last_percent != Some(progress.percent);
+
+        if step <= 1 || moved || bound {
+            callback(progress);
+            last_percent = Some(progress.percent);
+        }
+    }
+}
+
+pub(crate) fn handle(
+    stdout: ChildStdout,
+    mut output: Output,
+    mut callback: impl FnMut(Progress),
+    mut on_file: impl FnMut(&Path),
+) -> io::Result<ExtractSummary> {
+    let mut reader = BufReader::new(stdout);
+
+    loop {
+        let mut data = [0; 0x1000];
+        let count = reader.read(&mut data)?;
+
+        if count == 0 {
+            return Ok(output.finish(&mut callback, &mut on_file));
+        }
+
+        output.feed(&data[..count], &mut callback, &mut on_file);
+    }
+}
+
+/// Splits the stdout of an extraction into lines, keeping partial lines
+/// across reads, and tells progress bar updates apart from the other output.
+#[derive(Default)]
+pub(crate) struct Output {
+    pending: Vec<u8>,
+    /// `None` until something is reported, so that a first 0% is too.
+    last_progress: Option<Progress>,
+    summary: ExtractSummary,
+    /// Directory prefixed to the paths printed by `-info`, if it was passed.
+    files_root: Option<PathBuf>,
+    /// Whether `-percentage` was passed, which prints a bare percentage per
+    /// line instead of the progress bar.
+    percentage: bool,
+}
+
+impl Output {
+    /// Also reports the paths printed by `-info` under `files_root`.
+    pub(crate) fn with_files(files_root: PathBuf) -> Self {
+        Self {
+            files_root: Some(files_root),
+            ..Default::default()
+        }
+    }
+
+    /// Parses the bare percentages printed by `-percentage`.
+    pub(crate) fn with_percentage(mut self, percentage: bool) -> Self {
+        self.percentage = percentage;
+        self
+    }
+
+    /// Parses a chunk of output, calling `callback` whenever the progress
+    /// changes and `on_file` for each extracted file.
+    pub(crate) fn feed(
+        &mut self,
+        data: &[u8],
+        callback: &mut impl FnMut(Progress),
+        on_file: &mut impl FnMut(&Path),
+    ) {
+        self.pending.extend_from_slice(data);
+
+        while let Some(end) = self.pending.iter().position(|b| matches!(b, b'\r' | b'\n')) {
+            let line: Vec<u8> = self.pending.drain(..=end).take(end).collect();
+            self.line(&line, callback, on_file);
+        }
+
+        // The progress bar is redrawn in place, so the latest update is not
+        // terminated until the next one arrives.
+        if let Some(progress) = str::from_utf8(&self.pending).ok().and_then(parse_line) {
+            self.progress(progress, callback);
+        }
+    }
+
+    /// Parses what is left at the end of the output.
+    pub(crate) fn finish(
+        mut self,
+        callback: &mut impl FnMut(Progress),
+        on_file: &mut impl FnMut(&Path),
+    ) -> ExtractSummary {
+        let line = std::mem::take(&mut self.pending);
+        self.line(&line, callback, on_file);
+
+        self.summary
+    }
+
+    fn line(
+        &mut self,
+        line: &[u8],
+        callback: &mut impl FnMut(Progress),
+        on_file: &mut impl FnMut(&Path),
+    ) {
+        if let Some(progress) = str::from_utf8(line).ok().and_then(parse_line) {
+            return self.progress(progress, callback);
+        }
+
+        if let Some(percent) = self.percentage.then(|| parse_percentage(line)).flatten() {
+            let progress = Progress {
+                percent,
+                ..Default::default()
+            };
+
+            return self.progress(progress, callback);
+        }
+
+        let path = Path::new(OsStr::from_bytes(line));
+
+        if let Some(file) = self
+            .files_root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+        {
+            if !file.as_os_str().is_empty() {
+                on_file(&Path::new("/").join(file));
+            }
+
+            return;
+        }
+
+        self.summary.line(&String::from_utf8_lossy(line));
+    }
+
+    fn progress(&mut self, progress: Progress, callback: &mut impl FnMut(Progress)) {
+        diag!(trace, "parsed {progress:?}");
+
+        if self.last_progress != Some(progress) {
+            callback(progress);
+            self.last_progress = Some(progress);
+        }
+    }
+}
+
+/// Parses a line printed by `-percentage`, such as `42`.
+fn parse_percentage(line: &[u8]) -> Option<i32> {
+    str::from_utf8(line).ok()?.trim().parse().ok()
+}
+
+/// Parses the digits right before the `%` that ends `line`, such as the `5`
+/// of `1/20   5%`, if they are a percentage on their own.
+fn parse_percent(line: &str) -> Option<i32> {
+    let line = line.strip_suffix('%')?;
+    let before = line.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = &line[before.len()..];
+
+    if !(before.is_empty() || before.ends_with([' ', ']'])) {
+        return None;
+    }
+
+    digits
+        .parse()
+        .ok()
+        .filter(|percent| (0..=100).contains(percent))
+}
+
+/// Parses a progress bar line such as `[=====-     ] 1234/5678  21%`.
+///
+/// The start of the bar may be cut off on narrow terminals, as in
+/// `====-     ] 1234/5678  21%`.
+fn parse_line(line: &str) -> Option<Progress> {
+    let truncated = line.split_once(']').is_some_and(|(bar, _)| {
+        bar.chars()
+            .all(|c| matches!(c, '=' | '-' | '|' | '/' | '\\' | ' '))
+    });
+
+    if !(line.starts_with('[') || truncated) {
+        return None;
+    }
+
+    let percent = parse_percent(line)?;
+
+    let counter = line
+        .split_whitespace()
+        .rev()
+        .nth(1)
+        .and_then(|counter| counter.split_once('/'))
+        .and_then(|(done, total)| Some((done.parse().ok()?, total.parse().ok()?)));
+
+    Some(Progress {
+        percent,
+        done: counter.map(|(done, _)| done),
+        total: counter.map(|(_, total)| total),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    use super::{parse_line, percent_only, stepped, Output, Progress, RateEstimator};
+    use crate::{ExtractOptions, UnsquashfsError};
+
+    #[test]
+    fn test_parse_counters() {
+        assert_eq!(
+            parse_line("[=====-       ] 1234/5678  21%"),
+            Some(Progress {
+                percent: 21,
+                done: Some(1234),
+                total: Some(5678),
+            })
+        );
+        assert_eq!(
+            parse_line("[==============]  42%"),
+            Some(Progress {
+                percent: 42,
+                done: None,
+                total: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_rate_estimate() {
+        let mut estimator = RateEstimator::default();
+        let start = Instant::now();
+        let progress = |done| Progress {
+            percent: (done / 10) as i32,
+            done: Some(done),
+            total: Some(1000),
+        };
+
+        let stats = estimator.update(progress(0), start);
+        assert_eq!(stats.eta, None);
+
+        let stats = estimator.update(progress(100), start + Duration::from_secs(1));
+        assert_eq!(stats.rate_per_sec, 100.0);
+        assert_eq!(stats.elapsed, Duration::from_secs(1));
+        assert_eq!(stats.eta, None);
+
+        let stats = estimator.update(progress(400), start + Duration::from_secs(4));
+        assert_eq!(stats.rate_per_sec, 100.0);
+        assert_eq!(stats.eta, Some(Duration::from_secs(6)));
+
+        // A burst only moves the average part of the way.
+        let stats = estimator.update(progress(900), start + Duration::from_secs(5));
+        assert!(stats.rate_per_sec > 100.0 && stats.rate_per_sec < 500.0);
+
+        // Counters overshooting the total don't make the estimate negative.
+        let stats = estimator.update(progress(1100), start + Duration::from_secs(6));
+        assert_eq!(stats.eta, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_parse_large_and_truncated() {
+        assert_eq!(
+            parse_line("[=========-   ] 5000000000/6000000000  83%"),
+            Some(Progress {
+                percent: 83,
+                done: Some(5_000_000_000),
+                total: Some(6_000_000_000),
+            })
+        );
+        assert_eq!(
+            parse_line("===-  ] 12345/60000  20%"),
+            Some(Progress {
+                percent: 20,
+                done: Some(12345),
+                total: Some(60000),
+            })
+        );
+        assert_eq!(parse_line("squashfs-root/a 1/2  50%"), None);
+    }
+
+    #[test]
+    fn test_parse_percent_widths() {
+        for (line, percent) in [
+            ("[=         ] 1/20   5%", 5),
+            ("[====      ] 8/20  42%", 42),
+            ("[==========] 20/20 100%", 100),
+            ("[          ]0%", 0),
+        ] {
+            assert_eq!(parse_line(line).map(|p| p.percent), Some(percent), "{line}");
+        }
+
+        for line in [
+            "[",
+            "[%",
+            "[=] 1/2 %",
+            "[=] 1/2 x5%",
+            "[=] 1/2 101%",
+            "[=] 1/2 é%",
+            "[=] 50",
+        ] {
+            assert_eq!(parse_line(line), None, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_parse_percentage() {
+        let mut output = Output::default().with_percentage(true);
+        let mut percents = Vec::new();
+
+        for chunk in ["0\n2", "5\n", "25\n100\ncreated 1 file\n"] {
+            output.feed(
+                chunk.as_bytes(),
+                &mut |p: Progress| percents.push(p.percent),
+                &mut |_| {},
+            );
+        }
+
+        assert_eq!(percents, [0, 25, 100]);
+    }
+
+    #[test]
+    fn test_split_chunks() {
+        let files: Vec<String> = (0..40).map(|i| format!("/dir/file {i}")).collect();
+        let mut data = String::new();
+
+        for (i, file) in files.iter().enumerate() {
+            data.push_str(&format!(
+                "dest{file}\n[===   ] {i}/40 {:3}%\r",
+                i * 100 / 40
+            ));
+        }
+        data.push_str("\ncreated 40 files\n");
+
+        for size in [1, 3, 7, 64, data.len()] {
+            let mut output = Output::with_files("dest".into());
+            let (mut reported, mut percents) = (Vec::new(), Vec::new());
+            let mut callback = |p: Progress| percents.push(p.percent);
+            let mut on_file = |f: &Path| reported.push(f.to_string_lossy().into_owned());
+
+            for chunk in data.as_bytes().chunks(size) {
+                output.feed(chunk, &mut callback, &mut on_file);
+            }
+            let summary = output.finish(&mut callback, &mut on_file);
+
+            assert_eq!(reported, files);
+            assert_eq!(percents.len(), 40);
+            assert_eq!(summary.files, 40);
+        }
+    }
+
+    #[test]
+    fn test_split_anywhere() {
+        let bar = "[=     ] 10/400   2%\r[==    ] 120/400  30%\r[======] 400/400 100%\n";
+        let percentage = "2\n30\n100\n";
+
+        for (data, with_percentage) in [(bar, false), (percentage, true)] {
+            for at in 0..=data.len() {
+                let mut output = Output::default().with_percentage(with_percentage);
+                let mut percents = Vec::new();
+                let mut callback = |p: Progress| percents.push(p.percent);
+
+                output.feed(&data.as_bytes()[..at], &mut callback, &mut |_| {});
+                output.feed(&data.as_bytes()[at..], &mut callback, &mut |_| {});
+                output.finish(&mut callback, &mut |_| {});
+
+                assert_eq!(percents, [2, 30, 100], "split at {at} of {data:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_stepped() {
+        let mut reported = Vec::new();
+        let mut callback = stepped(5, percent_only(|percent| reported.push(percent)));
+
+        for percent in [1, 3, 5, 6, 9, 10, 12, 16, 97, 99, 100, 100] {
+            callback(Progress {
+                percent,
+                ..Default::default()
+            });
+        }
+        drop(callback);
+
+        assert_eq!(reported, [5, 10, 16, 97, 100]);
+    }
+
+    #[test]
+    fn test_stepped_from_zero() {
+        let mut reported = Vec::new();
+        let mut callback = stepped(10, percent_only(|percent| reported.push(percent)));
+
+        for percent in [0, 0, 4, 10, 15, 0, 3, 100] {
+            callback(Progress {
+                percent,
+                ..Default::default()
+            });
+        }
+        drop(callback);
+
+        assert_eq!(reported, [0, 10, 0, 100]);
+    }
+
+    #[test]
+    fn test_progress_step_range() {
+        assert!(ExtractOptions::new().progress_step(100).validate().is_ok());
+
+        for step in [0, 101] {
+            let err = ExtractOptions::new()
+                .progress_step(step)
+                .validate()
+                .unwrap_err();
+            assert!(matches!(err, UnsquashfsError::InvalidOption(_)));
+        }
+    }
+}
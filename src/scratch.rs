@@ -0,0 +1,77 @@
+use std::{
+    env, fs, mem,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{ExtractOptions, UnsquashfsError, XattrPolicy};
+
+/// Tells apart the scratch directories of concurrent runs.
+static SCRATCH_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory under [`env::temp_dir`] that an image is extracted into and
+/// thrown away after. Dropping it removes the directory as well, ignoring
+/// errors.
+pub(crate) struct Scratch {
+    path: PathBuf,
+}
+
+impl Scratch {
+    /// Creates `unsquashfs-wrapper-<kind>-<pid>-<id>`.
+    pub(crate) fn new(kind: &str) -> Result<Self, UnsquashfsError> {
+        // `unsquashfs` prints the canonical path it was given.
+        let path = env::temp_dir().canonicalize()?.join(format!(
+            "unsquashfs-wrapper-{kind}-{}-{}",
+            process::id(),
+            SCRATCH_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path)?;
+
+        Ok(Self { path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Options extracting into the directory, skipping xattrs since those
+    /// that can't be set don't matter for a throwaway copy.
+    pub(crate) fn options(&self) -> ExtractOptions {
+        ExtractOptions::new()
+            .dest(&self.path)
+            .xattrs(XattrPolicy::Skip)
+    }
+
+    /// Removes the directory and everything extracted into it.
+    pub(crate) fn remove(mut self) -> Result<(), UnsquashfsError> {
+        fs::remove_dir_all(mem::take(&mut self.path)).map_err(UnsquashfsError::Cleanup)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        // Empty once `remove` has taken care of it.
+        if !self.path.as_os_str().is_empty() {
+            fs::remove_dir_all(&self.path).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::Scratch;
+
+    #[test]
+    fn test_drop_scratch() {
+        let scratch = Scratch::new("test-drop").unwrap();
+        let path = scratch.path().to_path_buf();
+        fs::write(path.join("file"), "").unwrap();
+
+        drop(scratch);
+
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,170 @@
+use std::io;
+
+use crate::{Status, Unsquashfs, UnsquashfsError};
+
+impl Unsquashfs {
+    /// Stops the running `unsquashfs` with `SIGSTOP` until
+    /// [`Unsquashfs::resume`] is called. Only available on Unix.
+    ///
+    /// [`Unsquashfs::cancel`] still works while paused, as the process is
+    /// continued before being asked to exit. Fails with [`UnsquashfsError::Pending`] if
+    /// nothing is running.
+    pub fn pause(&self) -> Result<(), UnsquashfsError> {
+        let mut status = self.status.write().unwrap();
+
+        let percent = match *status {
+            Status::Working { percent } => percent,
+            Status::Paused { .. } | Status::Cancelling => {
+                return Err(UnsquashfsError::InvalidStatus(*status))
+            }
+            Status::Pending | Status::Cancelled | Status::Done | Status::Failed => {
+                return Err(UnsquashfsError::Pending)
+            }
+        };
+
+        self.signal(libc::SIGSTOP)?;
+        *status = Status::Paused { percent };
+
+        Ok(())
+    }
+
+    /// Continues an `unsquashfs` stopped by [`Unsquashfs::pause`] with
+    /// `SIGCONT`. Only available on Unix.
+    pub fn resume(&self) -> Result<(), UnsquashfsError> {
+        let mut status = self.status.write().unwrap();
+
+        let Status::Paused { percent } = *status else {
+            return Err(UnsquashfsError::InvalidStatus(*status));
+        };
+
+        self.signal(libc::SIGCONT)?;
+        *status = Status::Working { percent };
+
+        Ok(())
+    }
+
+    /// Sends `signal` to the process group of the running `unsquashfs`.
+    fn signal(&self, signal: libc::c_int) -> Result<(), UnsquashfsError> {
+        let pid = self.pid.lock().unwrap().ok_or(UnsquashfsError::Pending)?;
+
+        // SAFETY: killpg has no memory safety requirements.
+        if unsafe { libc::killpg(pid as libc::pid_t, signal) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends `SIGTERM` to the process group `pgid`, continuing it first in case
+/// it was paused, as a stopped process doesn't handle signals.
+pub(crate) fn terminate(pgid: u32) {
+    // SAFETY: killpg has no memory safety requirements.
+    unsafe {
+        libc::killpg(pgid as libc::pid_t, libc::SIGCONT);
+        libc::killpg(pgid as libc::pid_t, libc::SIGTERM);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        sync::{
+            atomic::{AtomicI32, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{test::fake_binary, ExtractOptions, Status, Unsquashfs, UnsquashfsError};
+
+    /// State of a process from `/proc/<pid>/stat`, such as `S` or `T`.
+    fn process_state(pid: u32) -> char {
+        let stat = fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+        let (_, rest) = stat.rsplit_once(')').unwrap();
+
+        rest.trim_start().chars().next().unwrap()
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let binary = fake_binary(
+            "pause",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        assert!(matches!(unsquashfs.pause(), Err(UnsquashfsError::Pending)));
+
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let pid = unsquashfs.pid.lock().unwrap().unwrap();
+
+        unsquashfs.pause().unwrap();
+        assert_eq!(unsquashfs.status(), Status::Paused { percent: 10 });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(process_state(pid), 'T');
+        assert!(unsquashfs.pause().is_err());
+
+        unsquashfs.resume().unwrap();
+        assert_eq!(unsquashfs.status(), Status::Working { percent: 10 });
+        thread::sleep(Duration::from_millis(50));
+        assert_ne!(process_state(pid), 'T');
+
+        unsquashfs.pause().unwrap();
+        unsquashfs.cancel_blocking().unwrap();
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+        t.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_progress_stops_while_paused() {
+        let binary = fake_binary(
+            "pause-progress",
+            "[ \"$1\" = -help ] && exit 0
+i=0; while [ $i -lt 100 ]; do i=$((i + 1)); printf \"[=     ] $i/100  $i%%\\r\"; sleep 0.01; done",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let last = Arc::new(AtomicI32::new(0));
+        let last_clone = last.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |p| last_clone.store(p, Ordering::SeqCst),
+            )
+        });
+
+        while last.load(Ordering::SeqCst) < 5 {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        unsquashfs.pause().unwrap();
+        // Let what was printed before stopping be read.
+        thread::sleep(Duration::from_millis(50));
+        let paused = last.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(last.load(Ordering::SeqCst), paused);
+        assert!(paused < 100);
+
+        unsquashfs.resume().unwrap();
+        t.join().unwrap().unwrap();
+        assert_eq!(unsquashfs.status(), Status::Done);
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{Unsquashfs, UnsquashfsError};
+
+/// Compression algorithm of a squashfs image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Lzma,
+    Lzo,
+    Lz4,
+    Xz,
+    Zstd,
+    Unknown(String),
+}
+
+impl From<&str> for Compression {
+    fn from(name: &str) -> Self {
+        match name {
+            "gzip" => Compression::Gzip,
+            "lzma" => Compression::Lzma,
+            "lzo" => Compression::Lzo,
+            "lz4" => Compression::Lz4,
+            "xz" => Compression::Xz,
+            "zstd" => Compression::Zstd,
+            other => Compression::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl Compression {
+    /// Name of the algorithm as `mksquashfs -comp` takes it.
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Lzma => "lzma",
+            Compression::Lzo => "lzo",
+            Compression::Lz4 => "lz4",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+            Compression::Unknown(name) => name,
+        }
+    }
+}
+
+/// Flags stored in the superblock of an image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SuperblockFlags {
+    pub exportable: bool,
+    pub inodes_compressed: bool,
+    pub data_compressed: bool,
+    pub fragments_compressed: bool,
+    pub always_use_fragments: bool,
+    pub xattrs_compressed: bool,
+    pub duplicates_removed: bool,
+}
+
+/// Filesystem metadata read from the superblock of an image (`unsquashfs -s`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuperblockInfo {
+    pub compression: Compression,
+    /// Options the image was compressed with, one per line as `unsquashfs`
+    /// prints them, such as `compression-level 19` for zstd or
+    /// `Dictionary size 1048576` for xz. `None` if the image was built with
+    /// the defaults or the binary doesn't print them.
+    pub compression_options: Option<String>,
+    pub block_size: u64,
+    pub inode_count: u32,
+    pub fragment_count: u32,
+    /// Creation or last append time.
+    pub created: SystemTime,
+    /// Size of the image in bytes.
+    pub filesystem_size: u64,
+    pub flags: SuperblockFlags,
+}
+
+impl Unsquashfs {
+    /// Reads the superblock of an image without extracting it (`unsquashfs -s`).
+    pub fn stat(&self, archive: impl AsRef<Path>) -> Result<SuperblockInfo, UnsquashfsError> {
+        self.stat_inner(archive.as_ref(), None)
+    }
+
+    /// Reads the superblock of an image starting at byte `offset` inside `archive`.
+    pub fn stat_at(
+        &self,
+        archive: impl AsRef<Path>,
+        offset: u64,
+    ) -> Result<SuperblockInfo, UnsquashfsError> {
+        self.stat_inner(archive.as_ref(), Some(offset))
+    }
+
+    pub(crate) fn stat_inner(
+        &self,
+        archive: &Path,
+        offset: Option<u64>,
+    ) -> Result<SuperblockInfo, UnsquashfsError> {
+        let args = self.image_args("-s", archive, offset)?;
+        let mut parser = SuperblockParser::default();
+
+        self.run_lines(&args, |line| parser.line(line))?;
+
+        parser.finish()
+    }
+}
+
+#[derive(Default)]
+struct SuperblockParser {
+    compression: Option<Compression>,
+    compression_options: Vec<String>,
+    /// Whether the last lines were about the compression, after which its
+    /// options are printed indented.
+    in_compression: bool,
+    block_size: Option<u64>,
+    inode_count: Option<u32>,
+    fragment_count: Option<u32>,
+    created: Option<SystemTime>,
+    filesystem_size: Option<u64>,
+    flags: SuperblockFlags,
+}
+
+impl SuperblockParser {
+    fn line(&mut self, line: &str) {
+        let in_compression = std::mem::take(&mut self.in_compression);
+
+        if in_compression && line.starts_with(char::is_whitespace) && !line.trim().is_empty() {
+            self.compression_options.push(line.trim().to_string());
+            self.in_compression = true;
+        } else if let Some(name) = line.strip_prefix("Compression ") {
+            self.compression = Some(Compression::from(name.trim()));
+            self.in_compression = true;
+        } else if let Some(size) = line.strip_prefix("Block size ") {
+            self.block_size = size.trim().parse().ok();
+        } else if let Some(count) = line.strip_prefix("Number of inodes ") {
+            self.inode_count = count.trim().parse().ok();
+        } else if let Some(count) = line.strip_prefix("Number of fragments ") {
+            self.fragment_count = count.trim().parse().ok();
+        } else if let Some(time) = line.strip_prefix("Creation or last append time ") {
+            self.created = parse_time(time.trim());
+        } else if let Some(size) = line.strip_prefix("Filesystem size ") {
+            self.filesystem_size = parse_size(size);
+        } else {
+            let flags = &mut self.flags;
+
+            match line.trim() {
+                "Filesystem is exportable via NFS" => flags.exportable = true,
+                "Inodes are compressed" => flags.inodes_compressed = true,
+                "Data is compressed" => flags.data_compressed = true,
+                "Fragments are compressed" => flags.fragments_compressed = true,
+                "Always-use-fragments option is specified" => flags.always_use_fragments = true,
+                "Xattrs are compressed" => flags.xattrs_compressed = true,
+                "Duplicates are removed" => flags.duplicates_removed = true,
+                _ => {}
+            }
+        }
+    }
+
+    fn finish(self) -> Result<SuperblockInfo, UnsquashfsError> {
+        let missing =
+            |field: &str| UnsquashfsError::Parse(format!("superblock is missing {field}"));
+
+        Ok(SuperblockInfo {
+            compression: self.compression.ok_or_else(|| missing("compression"))?,
+            compression_options: (!self.compression_options.is_empty())
+                .then(|| self.compression_options.join("\n")),
+            block_size: self.block_size.ok_or_else(|| missing("block size"))?,
+            inode_count: self.inode_count.ok_or_else(|| missing("inode count"))?,
+            fragment_count: self
+                .fragment_count
+                .ok_or_else(|| missing("fragment count"))?,
+            created: self.created.ok_or_else(|| missing("creation time"))?,
+            filesystem_size: self
+                .filesystem_size
+                .ok_or_else(|| missing("filesystem size"))?,
+            flags: self.flags,
+        })
+    }
+}
+
+/// Parses the filesystem size, printed either as
+/// `4096 bytes (4.00 Kbytes / 0.00 Mbytes)` or, by older versions, as
+/// `4.00 Kbytes (0.00 Mbytes)`.
+fn parse_size(size: &str) -> Option<u64> {
+    let mut tokens = size.split_whitespace();
+    let value = tokens.next()?;
+
+    match tokens.next()? {
+        "bytes" => value.parse().ok(),
+        "Kbytes" => value.parse::<f64>().ok().map(|k| (k * 1024.0) as u64),
+        _ => None,
+    }
+}
+
+/// Parses a `ctime(3)` style timestamp such as `Mon Sep 16 10:00:00 2024`,
+/// which `unsquashfs` prints in UTC because it is run with `TZ=UTC`.
+fn parse_time(time: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let mut tokens = time.split_whitespace().skip(1);
+    let month = tokens.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let day: i64 = tokens.next()?.parse().ok()?;
+
+    let mut clock = tokens.next()?.split(':').map(|v| v.parse::<i64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+
+    let year: i64 = tokens.next()?.parse().ok()?;
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Number of days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use super::SuperblockParser;
+    use crate::{test::fake_binary, Compression, Unsquashfs};
+
+    const STAT_4_4: &str = "\
+Found a valid SQUASHFS 4:0 superblock on test.squashfs.
+Creation or last append time Tue Feb 29 23:59:59 2000
+Filesystem size 4.50 Kbytes (0.00 Mbytes)
+Compression gzip
+Block size 131072
+Filesystem is not exportable via NFS
+Inodes are compressed
+Data is compressed
+Uids/Gids (Id table) are compressed
+Fragments are not compressed
+Always-use-fragments option is not specified
+Xattrs are compressed
+Duplicates are not removed
+Number of fragments 0
+Number of inodes 3
+Number of ids 1";
+
+    const STAT_4_6: &str = "\
+Found a valid SQUASHFS 4:0 superblock on test.squashfs.
+Creation or last append time Mon Sep 16 10:00:00 2024
+Filesystem size 4096 bytes (4.00 Kbytes / 0.00 Mbytes)
+Compression zstd
+Block size 131072
+Filesystem is exportable via NFS
+Inodes are compressed
+Data is compressed
+Number of fragments 1
+Number of inodes 5
+Number of ids 1
+Number of xattr ids 0";
+
+    #[test]
+    fn test_parse_superblock() {
+        let mut parser = SuperblockParser::default();
+        STAT_4_6.lines().for_each(|line| parser.line(line));
+        let info = parser.finish().unwrap();
+
+        assert_eq!(info.compression, Compression::Zstd);
+        assert_eq!(info.block_size, 131072);
+        assert_eq!(info.inode_count, 5);
+        assert_eq!(info.fragment_count, 1);
+        assert_eq!(info.filesystem_size, 4096);
+        assert_eq!(
+            info.created,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1726480800)
+        );
+        assert!(info.flags.exportable && info.flags.data_compressed);
+        assert!(!info.flags.duplicates_removed);
+    }
+
+    #[test]
+    fn test_parse_superblock_4_4() {
+        let mut parser = SuperblockParser::default();
+        STAT_4_4.lines().for_each(|line| parser.line(line));
+        let info = parser.finish().unwrap();
+
+        assert_eq!(info.compression, Compression::Gzip);
+        assert_eq!(info.inode_count, 3);
+        assert_eq!(info.fragment_count, 0);
+        assert_eq!(info.filesystem_size, 4608);
+        assert_eq!(
+            info.created,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(951868799)
+        );
+        assert!(!info.flags.exportable && !info.flags.fragments_compressed);
+        assert!(!info.flags.duplicates_removed);
+    }
+
+    #[test]
+    fn test_parse_incomplete_superblock() {
+        let mut parser = SuperblockParser::default();
+        parser.line("Compression xz");
+
+        assert!(parser.finish().is_err());
+    }
+
+    #[test]
+    fn test_parse_compression_options() {
+        let mut parser = SuperblockParser::default();
+        STAT_4_6
+            .replace(
+                "Compression zstd\n",
+                "Compression xz\n\tDictionary size 1048576\n\tFilters selected: x86\n",
+            )
+            .lines()
+            .for_each(|line| parser.line(line));
+        let info = parser.finish().unwrap();
+
+        assert_eq!(info.compression, Compression::Xz);
+        assert_eq!(
+            info.compression_options.as_deref(),
+            Some("Dictionary size 1048576\nFilters selected: x86")
+        );
+        assert_eq!(info.block_size, 131072);
+
+        let mut parser = SuperblockParser::default();
+        STAT_4_6.lines().for_each(|line| parser.line(line));
+        assert_eq!(parser.finish().unwrap().compression_options, None);
+    }
+
+    #[test]
+    fn test_stat() {
+        let binary = fake_binary("stat", &format!("cat <<'EOF'\n{STAT_4_6}\nEOF"));
+
+        let info = Unsquashfs::with_binary(binary)
+            .stat("testdata/test_extract.squashfs")
+            .unwrap();
+
+        assert_eq!(info.block_size, 131072);
+    }
+
+    #[test]
+    fn test_stat_chatty_stderr() {
+        // More than a pipe holds, written before anything on stdout.
+        let binary = fake_binary(
+            "stat-chatty",
+            &format!("head -c 262144 /dev/zero | tr '\\0' w >&2\ncat <<'EOF'\n{STAT_4_6}\nEOF"),
+        );
+
+        let info = Unsquashfs::with_binary(binary)
+            .stat("testdata/test_extract.squashfs")
+            .unwrap();
+
+        assert_eq!(info.inode_count, 5);
+    }
+}
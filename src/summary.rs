@@ -0,0 +1,227 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{EntryKind, ExtractOptions, Unsquashfs, UnsquashfsError};
+
+/// What an extraction wrote, from the summary `unsquashfs` prints on exit.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExtractSummary {
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub devices: u64,
+    /// Total size of the regular files under the extracted paths.
+    pub bytes: u64,
+    /// Number of processors `unsquashfs` extracted with, from the
+    /// `Parallel unsquashfs: Using 4 processors` line it prints, or else as
+    /// given to [`ExtractOptions::threads`].
+    pub processors: Option<usize>,
+    /// What `unsquashfs` printed to stderr about the files it skipped, one
+    /// line per error. Only filled in with [`ErrorMode::Ignore`].
+    ///
+    /// [`ErrorMode::Ignore`]: crate::ErrorMode::Ignore
+    pub errors: Vec<String>,
+}
+
+impl ExtractSummary {
+    /// Parses a summary line such as `created 3 files` or `created 1 directory`.
+    pub(crate) fn line(&mut self, line: &str) {
+        if let Some(rest) = line.trim().strip_prefix("Parallel unsquashfs: Using ") {
+            self.processors = rest.split_whitespace().next().and_then(|n| n.parse().ok());
+            return;
+        }
+
+        let mut tokens = line.split_whitespace();
+
+        if tokens.next() != Some("created") {
+            return;
+        }
+
+        let (Some(Ok(count)), Some(kind)) = (tokens.next().map(str::parse), tokens.next()) else {
+            return;
+        };
+
+        if kind.starts_with("file") {
+            self.files = count;
+        } else if kind.starts_with("director") {
+            self.dirs = count;
+        } else if kind.starts_with("symlink") {
+            self.symlinks = count;
+        } else if kind.starts_with("device") {
+            self.devices = count;
+        }
+    }
+
+    /// Fills in [`ExtractSummary::errors`] from the stderr of `unsquashfs`.
+    pub(crate) fn errors(&mut self, stderr: &str) {
+        self.errors = stderr
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect();
+    }
+
+    /// Fills in [`ExtractSummary::bytes`], which `unsquashfs` doesn't report,
+    /// from the destination of `options`.
+    pub(crate) fn count_bytes(&mut self, options: &ExtractOptions) {
+        let dest = options.dest_dir();
+
+        self.bytes = if options.paths.is_empty() {
+            file_bytes(dest)
+        } else {
+            options
+                .paths
+                .iter()
+                .map(|path| {
+                    let relative: PathBuf = path
+                        .components()
+                        .filter(|c| matches!(c, Component::Normal(_)))
+                        .collect();
+
+                    file_bytes(&dest.join(relative))
+                })
+                .sum()
+        };
+    }
+}
+
+impl Unsquashfs {
+    /// Tallies what extracting the whole image would write, from a listing
+    /// (`unsquashfs -ll`), without writing anything or needing a destination.
+    ///
+    /// Useful to check that an image is readable and fits before extracting
+    /// it. As in the summary of an extraction, the root directory is counted
+    /// in [`ExtractSummary::dirs`].
+    pub fn check(&self, archive: impl AsRef<Path>) -> Result<ExtractSummary, UnsquashfsError> {
+        let mut summary = ExtractSummary {
+            dirs: 1,
+            ..Default::default()
+        };
+
+        for entry in self.list_long(archive)? {
+            match entry.kind {
+                EntryKind::File => {
+                    summary.files += 1;
+                    summary.bytes += entry.size;
+                }
+                EntryKind::Directory => summary.dirs += 1,
+                EntryKind::Symlink { .. } => summary.symlinks += 1,
+                EntryKind::CharDevice { .. } | EntryKind::BlockDevice { .. } => {
+                    summary.devices += 1
+                }
+                EntryKind::Fifo | EntryKind::Socket => {}
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Total size of the regular files in an image, to compare against the
+    /// free space of the destination before extracting it.
+    ///
+    /// The superblock only records the compressed size of the image, so
+    /// this sums the sizes from a listing like [`Unsquashfs::check`]. Space
+    /// taken by directories and other metadata comes on top of it.
+    pub fn uncompressed_size(&self, archive: impl AsRef<Path>) -> Result<u64, UnsquashfsError> {
+        Ok(self.check(archive)?.bytes)
+    }
+}
+
+/// Size of the regular files under `path`, not following symlinks.
+fn file_bytes(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+
+    if metadata.is_file() {
+        return metadata.len();
+    }
+
+    if !metadata.is_dir() {
+        return 0;
+    }
+
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| file_bytes(&entry.path()))
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::ExtractSummary;
+    use crate::{test::fake_binary, Unsquashfs};
+
+    #[test]
+    fn test_parse_summary() {
+        let mut summary = ExtractSummary::default();
+
+        "\
+Parallel unsquashfs: Using 8 processors
+5 inodes (3 blocks) to write
+
+created 3 files
+created 1 directory
+created 1 symlink
+created 0 devices
+created 0 fifos
+created 0 sockets"
+            .lines()
+            .for_each(|line| summary.line(line));
+
+        assert_eq!(
+            summary,
+            ExtractSummary {
+                files: 3,
+                dirs: 1,
+                symlinks: 1,
+                devices: 0,
+                bytes: 0,
+                processors: Some(8),
+                errors: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let binary = fake_binary(
+            "check",
+            "cat <<'EOF'
+drwxr-xr-x root/root                38 2024-09-16 10:00 squashfs-root
+drwxr-xr-x root/root                38 2024-09-16 10:00 squashfs-root/etc
+-rw-r--r-- root/root               123 2024-09-16 10:00 squashfs-root/etc/os-release
+-rw-r--r-- root/root              4096 2024-09-16 10:00 squashfs-root/etc/fstab
+lrwxrwxrwx root/root                 7 2024-09-16 10:00 squashfs-root/bin -> usr/bin
+crw-rw-rw- root/root             1,  3 2024-09-16 10:00 squashfs-root/null
+EOF",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let summary = unsquashfs.check("testdata/test_extract.squashfs").unwrap();
+
+        assert_eq!(
+            summary,
+            ExtractSummary {
+                files: 2,
+                dirs: 2,
+                symlinks: 1,
+                devices: 1,
+                bytes: 4219,
+                processors: None,
+                errors: Vec::new(),
+            }
+        );
+        assert_eq!(
+            unsquashfs
+                .uncompressed_size("testdata/test_extract.squashfs")
+                .unwrap(),
+            4219
+        );
+    }
+}
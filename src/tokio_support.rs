@@ -0,0 +1,524 @@
+use std::{
+    ffi::OsString,
+    io::{Error, ErrorKind},
+    panic,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use pty_process::{Command, Pty, Size};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    process::{Child, ChildStderr},
+    sync::mpsc,
+    task, time,
+};
+use tokio_stream::{wrappers::UnboundedReceiverStream, Stream};
+
+use crate::{
+    child,
+    cleanup::Snapshot,
+    extraction_failed,
+    logging::{self, Span},
+    options::{check_dest, OnStderr},
+    progress::{percent_only, stepped},
+    signal, spawn_denied, written_estimate, Backend, ExtractOptions, Progress, Status, Unsquashfs,
+    UnsquashfsError, CLEARED_ENV,
+};
+
+/// Kills the child if the extraction future is dropped before it finishes.
+struct ChildGuard {
+    child: Child,
+    unsquashfs: Unsquashfs,
+}
+
+impl ChildGuard {
+    fn finish(&self, status: Status) {
+        self.unsquashfs.end(status, status == Status::Cancelled);
+    }
+
+    /// Asks the child to exit with `SIGTERM` and kills it if it is still
+    /// running after `grace`, like the blocking extractions do.
+    async fn stop(&mut self, grace: Duration) -> Result<(), Error> {
+        #[cfg(unix)]
+        if let Some(pid) = self.child.id().filter(|_| !grace.is_zero()) {
+            signal::terminate(pid);
+
+            if let Ok(wait) = time::timeout(grace, self.child.wait()).await {
+                return wait.map(|_| ());
+            }
+        }
+
+        self.child.kill().await
+    }
+
+    fn fail(&self, e: Error) -> Error {
+        self.finish(Status::Failed);
+        e
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        self.child.start_kill().ok();
+        *self.unsquashfs.pid.lock().unwrap() = None;
+
+        if self.unsquashfs.is_working() {
+            self.unsquashfs.end(Status::Cancelled, true);
+        }
+    }
+}
+
+/// Runs `f`, which spawns probes or walks the filesystem, on the blocking
+/// thread pool so that it doesn't hold up other tasks.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, UnsquashfsError> + Send + 'static,
+) -> Result<T, UnsquashfsError> {
+    match task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(e) => match e.try_into_panic() {
+            Ok(payload) => panic::resume_unwind(payload),
+            Err(e) => Err(Error::other(e).into()),
+        },
+    }
+}
+
+/// Removes what a cancelled extraction added to the destination, if
+/// [`ExtractOptions::cleanup_on_cancel`] took a snapshot of it.
+async fn cleanup(snapshot: Option<Snapshot>) -> Result<(), UnsquashfsError> {
+    match snapshot {
+        Some(snapshot) => {
+            blocking(move || snapshot.remove_new().map_err(UnsquashfsError::Cleanup)).await
+        }
+        None => Ok(()),
+    }
+}
+
+/// Reads all of `stderr`, passing each line to `on_stderr` as it arrives.
+async fn read_stderr(stderr: ChildStderr, on_stderr: Option<OnStderr>) -> String {
+    let mut stderr = BufReader::new(stderr);
+    let mut buf = String::new();
+    let mut line = Vec::new();
+
+    while let Ok(1..) = stderr.read_until(b'\n', &mut line).await {
+        let text = String::from_utf8_lossy(&line);
+
+        if let Some(on_stderr) = &on_stderr {
+            on_stderr.call(text.trim_end_matches(['\r', '\n']));
+        }
+
+        buf.push_str(&text);
+        line.clear();
+    }
+
+    buf
+}
+
+impl Unsquashfs {
+    /// Extracts an image like [`Unsquashfs::extract_with`], without blocking
+    /// the async runtime.
+    ///
+    /// [`Unsquashfs::cancel`] and [`UnsquashfsError::Busy`] work the same as
+    /// for the blocking API, and dropping the returned future kills the
+    /// spawned `unsquashfs` process.
+    pub async fn extract_async(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+        callback: impl FnMut(i32),
+    ) -> Result<(), UnsquashfsError> {
+        let (archive, span) = (
+            archive.as_ref(),
+            Span::extract(archive.as_ref(), options.dest_dir()),
+        );
+
+        span.instrument(self.extract_async_inner(archive, options, &span, callback))
+            .await
+    }
+
+    async fn extract_async_inner(
+        &self,
+        archive: &Path,
+        options: &ExtractOptions,
+        span: &Span,
+        callback: impl FnMut(i32),
+    ) -> Result<(), UnsquashfsError> {
+        let mut callback = stepped(options.progress_step, percent_only(callback));
+
+        let mut options = options.clone();
+        options.percentage = true;
+        options.validate()?;
+
+        let (unsquashfs, archive_path) = (self.clone(), archive.to_path_buf());
+        let preflight_options = options.clone();
+        blocking(move || unsquashfs.preflight(&archive_path, &preflight_options)).await?;
+
+        let claim = self.begin()?;
+
+        let (unsquashfs, archive_path) = (self.clone(), archive.to_path_buf());
+        let (binary, options, args, mut snapshot) =
+            blocking(move || unsquashfs.prepare(&archive_path, &options)).await?;
+
+        let pty = Pty::new()?;
+        pty.resize(Size::new(30, 80))?;
+
+        diag!(debug, "spawning {}", logging::command_line(&binary, &args));
+
+        let mut command = Command::new(&binary);
+        command.args(args);
+
+        for key in CLEARED_ENV {
+            command.env_remove(key);
+        }
+        command.envs(&options.env);
+
+        let pre_exec = child::pre_exec(&options)?;
+        let blame = pre_exec.is_none().then_some(binary.as_path());
+
+        if let Some(pre_exec) = pre_exec {
+            // SAFETY: it only makes system calls, which is fine after fork.
+            unsafe { command.pre_exec(pre_exec) };
+        }
+
+        let child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn(&pty.pts()?)
+            .map_err(spawn_denied(blame))?;
+
+        *self.pid.lock().unwrap() = child.id();
+        claim.spawned();
+
+        let mut guard = ChildGuard {
+            child,
+            unsquashfs: self.clone(),
+        };
+
+        let mut stdout = guard
+            .child
+            .stdout
+            .take()
+            .ok_or_else(|| Error::new(ErrorKind::BrokenPipe, "Failed to get stdout"))?;
+
+        let stderr = guard
+            .child
+            .stderr
+            .take()
+            .ok_or_else(|| Error::new(ErrorKind::BrokenPipe, "Failed to get stderr"))?;
+        let stderr = tokio::spawn(read_stderr(stderr, options.on_stderr.clone()));
+
+        let mut output = options.output()?;
+        let mut data = [0; 0x1000];
+        let mut interval = time::interval(Duration::from_millis(10));
+        let start = Instant::now();
+        let mut last_progress_at = start;
+        let mut last_progress = Progress::default();
+
+        loop {
+            tokio::select! {
+                count = stdout.read(&mut data) => {
+                    let count = count.map_err(|e| guard.fail(e))?;
+
+                    if count == 0 {
+                        break;
+                    }
+
+                    output.feed(
+                        &data[..count],
+                        &mut |progress| {
+                            last_progress_at = Instant::now();
+                            span.record_percent(progress.percent);
+                            self.set_percent(progress);
+                            last_progress = progress;
+                            callback(progress);
+                        },
+                        &mut |path| {
+                            if let Some(on_file) = &options.on_file {
+                                on_file.call(path);
+                            }
+                        },
+                    );
+                }
+                _ = interval.tick() => {
+                    if self.cancel.load(Ordering::SeqCst) {
+                        self.cancel.store(false, Ordering::SeqCst);
+                        guard.stop(options.cancel_grace).await.map_err(|e| guard.fail(e))?;
+                        guard.finish(Status::Cancelled);
+                        return cleanup(snapshot.take()).await;
+                    }
+
+                    // A paused extraction is not stuck.
+                    if matches!(self.status(), Status::Paused { .. }) {
+                        last_progress_at = Instant::now();
+                    }
+
+                    if options.time_limit.is_some_and(|limit| start.elapsed() >= limit) {
+                        let elapsed = start.elapsed();
+                        let percent = match self.status() {
+                            Status::Working { percent } | Status::Paused { percent } => percent,
+                            _ => 0,
+                        };
+
+                        guard.stop(options.cancel_grace).await.map_err(|e| guard.fail(e))?;
+                        guard.finish(Status::Cancelled);
+                        cleanup(snapshot.take()).await?;
+                        return Err(UnsquashfsError::TimedOut { elapsed, percent });
+                    }
+
+                    if let Some(timeout) = options.timeout {
+                        if last_progress_at.elapsed() >= timeout {
+                            guard.stop(options.cancel_grace).await.map_err(|e| guard.fail(e))?;
+                            guard.finish(Status::Cancelled);
+                            cleanup(snapshot.take()).await?;
+                            return Err(UnsquashfsError::Timeout(timeout));
+                        }
+                    }
+                }
+            }
+        }
+
+        let buf = options.filter_stderr(stderr.await.unwrap_or_default());
+        logging::stderr(&buf);
+
+        let wait = guard.child.wait().await.map_err(|e| guard.fail(e))?;
+
+        if !options.error_mode.succeeded(wait) {
+            guard.finish(Status::Failed);
+            return Err(extraction_failed(
+                wait,
+                buf,
+                Some(options.dest_dir()),
+                written_estimate(archive, &options, last_progress.percent),
+            ));
+        }
+
+        guard.finish(Status::Done);
+
+        if let Some(progress) = last_progress.completed() {
+            callback(progress);
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the binary to extract `archive` with and the arguments for
+    /// `options` as the installed version understands them, checks the
+    /// destination and takes its snapshot if it is to be cleaned up.
+    fn prepare(
+        &self,
+        archive: &Path,
+        options: &ExtractOptions,
+    ) -> Result<(PathBuf, ExtractOptions, Vec<OsString>, Option<Snapshot>), UnsquashfsError> {
+        let (binary, backend) = self.binary()?;
+
+        let options = match backend {
+            Backend::Unsquashfs => self.degrade(options)?,
+            Backend::Rdsquashfs => options.clone(),
+        };
+        let args = options.args(archive, backend)?;
+
+        if backend == Backend::Unsquashfs {
+            self.require_flags(&options.required_flags())?;
+        }
+
+        check_dest(options.dest_dir())?;
+
+        let snapshot = match options.cleanup_on_cancel {
+            true => Some(Snapshot::take(options.dest_dir()).map_err(UnsquashfsError::Cleanup)?),
+            false => None,
+        };
+
+        Ok((binary, options, args, snapshot))
+    }
+
+    /// Extracts an image in a background task, returning a [`Stream`] of the
+    /// progress percentages instead of taking a callback.
+    ///
+    /// The stream ends once `unsquashfs` exits; if the extraction fails, the
+    /// error is yielded as the last item. Dropping the stream does not stop
+    /// the extraction, use [`Unsquashfs::cancel`] for that.
+    pub fn extract_progress_stream(
+        &self,
+        archive: impl AsRef<Path>,
+        options: &ExtractOptions,
+    ) -> impl Stream<Item = Result<i32, UnsquashfsError>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let unsquashfs = self.clone();
+        let archive = archive.as_ref().to_path_buf();
+        let options = options.clone();
+
+        tokio::spawn(async move {
+            let progress_tx = tx.clone();
+            let res = unsquashfs
+                .extract_async(archive, &options, move |progress| {
+                    progress_tx.send(Ok(progress)).ok();
+                })
+                .await;
+
+            if let Err(e) = res {
+                tx.send(Err(e)).ok();
+            }
+        });
+
+        UnboundedReceiverStream::new(rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env::temp_dir,
+        fs,
+        path::Path,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use tokio::time;
+    use tokio_stream::StreamExt;
+
+    use crate::{test::fake_binary, ExtractOptions, Status, Unsquashfs, UnsquashfsError};
+
+    #[tokio::test]
+    async fn test_extract_async() {
+        let output = temp_dir().join("unsqfs-wrap-test-extract-async");
+        fs::create_dir_all(&output).unwrap();
+
+        let options = ExtractOptions::new().dest(&output);
+        let mut progress = Vec::new();
+        Unsquashfs::default()
+            .extract_async("testdata/test_extract.squashfs", &options, |c| {
+                progress.push(c)
+            })
+            .await
+            .unwrap();
+
+        assert!(progress.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(progress.last(), Some(&100));
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extract_progress_stream() {
+        let output = temp_dir().join("unsqfs-wrap-test-extract-stream");
+        fs::create_dir_all(&output).unwrap();
+
+        let options = ExtractOptions::new().dest(&output);
+        let progress = Unsquashfs::default()
+            .extract_progress_stream("testdata/test_extract.squashfs", &options)
+            .collect::<Result<Vec<_>, _>>()
+            .await
+            .unwrap();
+
+        assert!(progress.windows(2).all(|w| w[0] < w[1]));
+        fs::remove_dir_all(output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drop_kills_child() {
+        let binary = fake_binary(
+            "async-slow",
+            "[ \"$1\" = -help ] && exit 0
+echo $$ > \"$0.pid\"
+trap '' HUP
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let options = ExtractOptions::new();
+        let mut progress = Vec::new();
+
+        let extract = unsquashfs.extract_async("testdata/test_extract.squashfs", &options, |c| {
+            progress.push(c)
+        });
+        assert!(time::timeout(Duration::from_millis(200), extract)
+            .await
+            .is_err());
+
+        assert_eq!(progress, [10]);
+        assert_eq!(unsquashfs.status(), Status::Cancelled);
+
+        // Killed, not just hung up on when the pty went away, although
+        // nothing may have reaped it yet.
+        let pid = fs::read_to_string(binary.with_extension("pid")).unwrap();
+        let stat = Path::new("/proc").join(pid.trim()).join("stat");
+        let exited = || {
+            fs::read_to_string(&stat).map_or(true, |stat| {
+                stat.rsplit_once(')')
+                    .is_some_and(|(_, rest)| rest.trim_start().starts_with(['Z', 'X']))
+            })
+        };
+
+        for _ in 0..100 {
+            if exited() {
+                return;
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("`unsquashfs` is still running");
+    }
+
+    #[tokio::test]
+    async fn test_probes_dont_block_runtime() {
+        let binary = fake_binary(
+            "async-slow-help",
+            "[ \"$1\" = -help ] && { sleep 0.5; exit 0; }
+printf '[==========] 1/1 100%%\\n'",
+        );
+
+        let start = Instant::now();
+        let (unsquashfs, options) = (Unsquashfs::with_binary(binary), ExtractOptions::new());
+        let extract = unsquashfs.extract_async("testdata/test_extract.squashfs", &options, |_| {});
+        // On the same single-threaded runtime as the extraction.
+        let tick = async {
+            time::sleep(Duration::from_millis(50)).await;
+            start.elapsed()
+        };
+
+        let (res, ticked) = tokio::join!(extract, tick);
+        res.unwrap();
+        assert!(
+            ticked < Duration::from_millis(300),
+            "ticked after {ticked:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_busy() {
+        let binary = fake_binary(
+            "async-busy",
+            "[ \"$1\" = -help ] && exit 0
+printf '[=     ] 1/9  10%%\\r'; sleep 0.2",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.extract_with(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+        });
+
+        while !unsquashfs.is_working() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // The blocking extraction keeps the instance to itself.
+        let res = unsquashfs
+            .extract_async(
+                "testdata/test_extract.squashfs",
+                &ExtractOptions::new(),
+                |_| {},
+            )
+            .await;
+        assert!(matches!(res, Err(UnsquashfsError::Busy)));
+
+        t.join().unwrap().unwrap();
+        assert_eq!(unsquashfs.status(), Status::Done);
+    }
+}
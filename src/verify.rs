@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+
+use crate::{progress::percent_only, scratch::Scratch, Status, Unsquashfs, UnsquashfsError};
+
+impl Unsquashfs {
+    /// Reads every file of an image to check that it is intact, reporting the
+    /// percentage like [`Unsquashfs::extract`].
+    ///
+    /// The image is extracted into a scratch directory under
+    /// [`std::env::temp_dir`] which is removed afterwards, so it needs as much
+    /// room there as the image holds. A data block that can't be read or
+    /// decompressed fails with [`UnsquashfsError::Corrupted`], naming the
+    /// file it belongs to, and
+    /// [`Unsquashfs::cancel`] makes it return [`UnsquashfsError::Cancelled`].
+    pub fn verify(
+        &self,
+        archive: impl AsRef<Path>,
+        threads: Option<usize>,
+        callback: impl FnMut(i32),
+    ) -> Result<(), UnsquashfsError> {
+        let scratch = Scratch::new("verify")?;
+        let mut options = scratch.options();
+
+        if let Some(threads) = threads {
+            options = options.threads(threads);
+        }
+
+        options.percentage = true;
+
+        let res = self.extract_inner(
+            archive.as_ref(),
+            &options,
+            || {},
+            percent_only(callback),
+            |_| {},
+        );
+
+        let res = match res {
+            Ok(_) if self.status() == Status::Cancelled => Err(UnsquashfsError::Cancelled),
+            Ok(_) => Ok(()),
+            Err(UnsquashfsError::Corrupted { path: None, stderr }) => {
+                Err(UnsquashfsError::Corrupted {
+                    path: corrupted_path(&stderr, scratch.path()),
+                    stderr,
+                })
+            }
+            Err(UnsquashfsError::Failure {
+                source,
+                stderr,
+                exit_code,
+                signal,
+            }) => match corrupted_path(&stderr, scratch.path()) {
+                Some(path) => Err(UnsquashfsError::Corrupted {
+                    path: Some(path),
+                    stderr,
+                }),
+                None => Err(UnsquashfsError::Failure {
+                    source,
+                    stderr,
+                    exit_code,
+                    signal,
+                }),
+            },
+            Err(e) => Err(e),
+        };
+
+        // Leftovers only matter if the image itself is fine.
+        let cleanup = scratch.remove();
+        res.and(cleanup)
+    }
+}
+
+/// Finds the file whose data `unsquashfs` failed to read, from a line such
+/// as `Failed to read or decompress data block for file <dest>/etc/fstab`,
+/// as a path inside the image under `dest`.
+fn corrupted_path(stderr: &str, dest: &Path) -> Option<PathBuf> {
+    stderr.lines().find_map(|line| {
+        let lower = line.to_ascii_lowercase();
+
+        if !lower.contains("failed to read") && !lower.contains("decompress") {
+            return None;
+        }
+
+        let (_, file) = line.split_once(" file ")?;
+        let file = Path::new(file.trim().trim_end_matches(['.', ',']));
+
+        let relative = file
+            .strip_prefix(dest)
+            .or_else(|_| file.strip_prefix("squashfs-root"))
+            .unwrap_or(file);
+
+        Some(Path::new("/").join(relative))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{test::fake_binary, Status, Unsquashfs, UnsquashfsError};
+
+    #[test]
+    fn test_verify() {
+        let binary = fake_binary(
+            "verify",
+            "[ \"$1\" = -help ] && echo '-no-xattrs' && exit 0
+printf '[=====     ] 1/2  50%%\\r[==========] 2/2 100%%\\n'",
+        );
+
+        let mut progress = Vec::new();
+        Unsquashfs::with_binary(binary)
+            .verify("testdata/test_extract.squashfs", None, |p| progress.push(p))
+            .unwrap();
+
+        assert_eq!(progress, [50, 100]);
+    }
+
+    #[test]
+    fn test_verify_corrupted() {
+        let binary = fake_binary(
+            "verify-corrupted",
+            "[ \"$1\" = -help ] && echo '-no-xattrs' && exit 0
+while [ \"$1\" != -d ]; do shift; done
+echo \"$2\" > \"$0.dest\"
+echo 'zstd uncompress failed with error code 20' >&2
+echo \"Failed to read or decompress data block for file $2/etc/os-release\" >&2
+exit 1",
+        );
+
+        let err = Unsquashfs::with_binary(&binary)
+            .verify("testdata/test_extract.squashfs", Some(2), |_| {})
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            UnsquashfsError::Corrupted { path: Some(path), .. } if path == Path::new("/etc/os-release")
+        ));
+
+        // The scratch directory is gone even though verifying failed.
+        let scratch = fs::read_to_string(binary.with_extension("dest")).unwrap();
+        assert!(!PathBuf::from(scratch.trim_end()).exists());
+    }
+
+    #[test]
+    fn test_verify_corrupted_filesystem() {
+        let binary = fake_binary(
+            "verify-corrupted-fs",
+            "[ \"$1\" = -help ] && echo '-no-xattrs' && exit 0
+while [ \"$1\" != -d ]; do shift; done
+echo \"Failed to read or decompress data block for file $2/usr/bin/sh\" >&2
+echo 'FATAL ERROR: Filesystem corrupted?' >&2
+exit 1",
+        );
+
+        let err = Unsquashfs::with_binary(&binary)
+            .verify("testdata/test_extract.squashfs", None, |_| {})
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            UnsquashfsError::Corrupted { path: Some(path), stderr }
+                if path == Path::new("/usr/bin/sh") && stderr.contains("corrupted")
+        ));
+    }
+
+    #[test]
+    fn test_verify_failure_before_cleanup() {
+        // Removing the scratch directory itself makes cleaning it up fail.
+        let binary = fake_binary(
+            "verify-failure-cleanup",
+            "[ \"$1\" = -help ] && echo '-no-xattrs' && exit 0
+while [ \"$1\" != -d ]; do shift; done
+rm -rf \"$2\"
+echo 'FATAL ERROR: Filesystem corrupted?' >&2
+exit 1",
+        );
+
+        let err = Unsquashfs::with_binary(&binary)
+            .verify("testdata/test_extract.squashfs", None, |_| {})
+            .unwrap_err();
+
+        assert!(matches!(err, UnsquashfsError::Corrupted { path: None, .. }));
+    }
+
+    #[test]
+    fn test_verify_cancel() {
+        let binary = fake_binary(
+            "verify-cancel",
+            "[ \"$1\" = -help ] && echo '-no-xattrs' && exit 0
+echo \"$4\" > \"$0.dest\"
+printf '[=     ] 1/9  10%%\\r'; exec sleep 10",
+        );
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        let unsquashfs_clone = unsquashfs.clone();
+        let t = thread::spawn(move || {
+            unsquashfs_clone.verify("testdata/test_extract.squashfs", None, |_| {})
+        });
+
+        while !matches!(unsquashfs.status(), Status::Working { percent: 10 }) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        unsquashfs.cancel().unwrap();
+        assert!(matches!(t.join().unwrap(), Err(UnsquashfsError::Cancelled)));
+
+        let scratch = fs::read_to_string(binary.with_extension("dest")).unwrap();
+        assert!(!PathBuf::from(scratch.trim_end()).exists());
+    }
+}
@@ -0,0 +1,206 @@
+use std::{fmt, process, str::FromStr};
+
+use crate::{denied, Backend, Unsquashfs, UnsquashfsError};
+
+/// Version of the installed squashfs-tools, e.g. `4.6.1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = UnsquashfsError;
+
+    /// Parses `4.6.1` or `4.4`, ignoring suffixes such as `4.6-git`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || UnsquashfsError::Parse(format!("invalid version `{s}`"));
+
+        let mut parts = s.split('.').map(|part| {
+            let digits = part
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(part, |end| &part[..end]);
+            digits.parse::<u32>()
+        });
+
+        let major = parts.next().ok_or_else(invalid)?.map_err(|_| invalid())?;
+        let minor = parts.next().ok_or_else(invalid)?.map_err(|_| invalid())?;
+        let patch = parts.next().and_then(|p| p.ok()).unwrap_or(0);
+
+        Ok(Version::new(major, minor, patch))
+    }
+}
+
+impl Unsquashfs {
+    /// Returns the version of the installed `unsquashfs` (`unsquashfs -version`),
+    /// running it only once per instance.
+    ///
+    /// Builds too old to know `-version` are asked for their `-help` instead,
+    /// and `rdsquashfs` for its `--version`, which reports the version of
+    /// squashfs-tools-ng rather than squashfs-tools.
+    pub fn version(&self) -> Result<Version, UnsquashfsError> {
+        if let Some(version) = self.version.get() {
+            return Ok(*version);
+        }
+
+        let (binary, backend) = self.binary()?;
+        let flag = match backend {
+            Backend::Unsquashfs => "-version",
+            Backend::Rdsquashfs => "--version",
+        };
+
+        let output = process::Command::new(&binary)
+            .arg(flag)
+            .output()
+            .map_err(denied(Some(&binary)))?;
+
+        let mut banner = String::from_utf8_lossy(&output.stdout).into_owned();
+        banner.push_str(&String::from_utf8_lossy(&output.stderr));
+
+        let version = match parse_version(&banner) {
+            Some(version) => Some(version),
+            None if backend == Backend::Unsquashfs => parse_version(self.help()?),
+            None => None,
+        }
+        .ok_or_else(|| UnsquashfsError::Parse(format!("no version in `{flag}` output")))?;
+
+        Ok(*self.version.get_or_init(|| version))
+    }
+}
+
+/// Finds the version in the first line of `output` that has one.
+fn parse_version(output: &str) -> Option<Version> {
+    output.lines().find_map(parse_version_line)
+}
+
+/// Parses a banner such as `unsquashfs version 4.6.1 (2023/03/25)` or
+/// `rdsquashfs (squashfs-tools-ng) 1.2.0`.
+fn parse_version_line(line: &str) -> Option<Version> {
+    let lower = line.to_ascii_lowercase();
+
+    // Don't mistake numbers in the descriptions of `-help` for a version.
+    if !lower.contains("version") && !lower.contains("squashfs-tools") {
+        return None;
+    }
+
+    line.split_whitespace()
+        .filter(|token| token.starts_with(|c: char| c.is_ascii_digit()) && token.contains('.'))
+        .find_map(|token| token.parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::parse_version_line;
+    use crate::{test::fake_binary, Backend, Unsquashfs, Version};
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version_line("unsquashfs version 4.6.1 (2023/03/25)"),
+            Some(Version::new(4, 6, 1))
+        );
+        assert_eq!(
+            parse_version_line("unsquashfs version 4.4 (2019/08/29)"),
+            Some(Version::new(4, 4, 0))
+        );
+        assert_eq!(
+            parse_version_line("unsquashfs version 4.5-git (2021/06/06)"),
+            Some(Version::new(4, 5, 0))
+        );
+        assert_eq!(
+            parse_version_line("unsquashfs version 4.6.1-1ubuntu0.1 (2023/03/25)"),
+            Some(Version::new(4, 6, 1))
+        );
+        assert_eq!(
+            parse_version_line("rdsquashfs (squashfs-tools-ng) 1.2.0"),
+            Some(Version::new(1, 2, 0))
+        );
+        assert_eq!(
+            parse_version_line("copyright (C) 2023 Phillip Lougher"),
+            None
+        );
+        assert_eq!(
+            parse_version_line("\t-da[ta-queue] <size>\tset to <size> Mbytes, default 256.0"),
+            None
+        );
+        assert!(Version::new(4, 4, 0) < Version::new(4, 6, 1));
+    }
+
+    #[test]
+    fn test_version() {
+        let binary = fake_binary(
+            "version",
+            "printf 'unsquashfs version 4.5.1 (2022/03/17)\\ncopyright (C) 2022 Phillip Lougher\\n'",
+        );
+
+        assert_eq!(
+            Unsquashfs::with_binary(binary).version().unwrap(),
+            Version::new(4, 5, 1)
+        );
+    }
+
+    #[test]
+    fn test_version_cached() {
+        let binary = fake_binary(
+            "version-cached",
+            "echo >> \"$0.runs\"
+echo 'unsquashfs version 4.6.1 (2023/03/25)'",
+        );
+        fs::remove_file(binary.with_extension("runs")).ok();
+
+        let unsquashfs = Unsquashfs::with_binary(&binary);
+        assert_eq!(unsquashfs.version().unwrap(), Version::new(4, 6, 1));
+        assert_eq!(unsquashfs.clone().version().unwrap(), Version::new(4, 6, 1));
+
+        let runs = fs::read_to_string(binary.with_extension("runs")).unwrap();
+        assert_eq!(runs.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_version_from_help() {
+        let binary = fake_binary(
+            "version-ancient",
+            "[ \"$1\" = -help ] && { echo 'unsquashfs version 4.0 (2009/04/05)'; echo 'SYNTAX: unsquashfs [options]'; exit 1; }
+echo 'unsquashfs: invalid option' >&2; exit 1",
+        );
+
+        assert_eq!(
+            Unsquashfs::with_binary(binary).version().unwrap(),
+            Version::new(4, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_version_rdsquashfs() {
+        let binary = fake_binary(
+            "version-ng",
+            "[ \"$1\" = --version ] || exit 1
+printf 'rdsquashfs (squashfs-tools-ng) 1.3.0\nCopyright (c) 2019 David Oberhollenzer et al\n'",
+        );
+
+        let unsquashfs = Unsquashfs {
+            backend: Some(Backend::Rdsquashfs),
+            ..Unsquashfs::with_binary(binary)
+        };
+        assert_eq!(unsquashfs.version().unwrap(), Version::new(1, 3, 0));
+    }
+}